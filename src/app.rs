@@ -1,5 +1,20 @@
+use crate::config::Config;
+use crate::edit::Edit;
+use crate::ex::Completion;
+use crate::history::InputHistory;
+use crate::journal::Journal;
+use crate::keymap::Context;
+use crate::substitute::Review;
+use crate::vi::{Find, History, Pending, Registers};
+use crate::worker::Worker;
 use serde::{Deserialize, Serialize};
-use std::{env, error, fs, path::PathBuf};
+use std::{
+    env, error, fs,
+    ops::{Range, RangeInclusive},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
 
@@ -7,13 +22,59 @@ pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
 pub enum Mode {
     Normal,
     Insert,
+    Replace,
+    /// Character-wise selection over the query.
+    Visual,
+    VisualLine,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum Window {
+    /// The settings panel.
     Options,
+    #[default]
     Search,
     Command,
+    /// The Ctrl-r popup over past queries.
+    History,
+    /// Substitutions shown in the preview pane, waiting to be accepted or rejected.
+    Review,
+    /// Past batches of file changes, the highlighted one shown in the preview pane.
+    Journal,
+}
+
+/// A single ripgrep hit. `column` is 1-based like `rg --column`, and `submatches`
+/// are byte ranges into `text`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Match {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    pub submatches: Vec<Range<usize>>,
+    #[serde(default)]
+    pub score: i64,
+    #[serde(default)]
+    pub marked: bool,
+    /// A line around a match listed for `context`, which can't be marked or acted on.
+    #[serde(default)]
+    pub context: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Matcher {
+    #[default]
+    Regex,
+    Fuzzy,
+}
+
+/// What the query is matched against.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Source {
+    #[default]
+    Content,
+    Files,
+    Stdin,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,10 +82,38 @@ pub struct Search {
     pub cursor: usize,
     pub query: Vec<char>,
     pub mode: Mode,
-    pub result: Vec<String>,
+    #[serde(default)]
+    pub source: Source,
+    #[serde(default)]
+    pub matcher: Matcher,
+    pub result: Vec<Match>,
     pub preview: String,
+    /// Matched ranges of every preview line, worked out again whenever it's shown.
+    #[serde(skip)]
+    pub highlights: Vec<Vec<Range<usize>>>,
     pub line: usize,
     pub scroll: usize,
+    /// Row where the current `V` selection started.
+    #[serde(default)]
+    pub anchor: usize,
+    #[serde(skip)]
+    pub searching: bool,
+    /// Hits of the current search left out because they aren't valid UTF-8.
+    #[serde(skip)]
+    pub skipped: usize,
+    /// When the current results were searched for.
+    #[serde(skip)]
+    pub searched_at: Option<SystemTime>,
+    /// The last `f`, `t`, `F` or `T`, for `;` and `,`.
+    #[serde(skip)]
+    pub find: Option<Find>,
+    /// Query position where the current `v` selection started.
+    #[serde(skip)]
+    pub query_anchor: usize,
+    /// Characters overwritten in replace mode, so backspace can put them back. `None`
+    /// stands for a character appended past the end.
+    #[serde(skip)]
+    pub replaced: Vec<Option<char>>,
 }
 
 impl Default for Search {
@@ -33,80 +122,258 @@ impl Default for Search {
             cursor: 0,
             query: Vec::new(),
             mode: Mode::Normal,
+            source: Source::default(),
+            matcher: Matcher::default(),
             result: Vec::new(),
             preview: String::new(),
+            highlights: Vec::new(),
             line: 0,
             scroll: 0,
+            anchor: 0,
+            searching: false,
+            skipped: 0,
+            searched_at: None,
+            find: None,
+            query_anchor: 0,
+            replaced: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Command {
-    pub cursor: usize,
-    pub query: Vec<char>,
-}
+impl Search {
+    /// Rows covered by the `V` selection, in order.
+    pub fn visual_range(&self) -> RangeInclusive<usize> {
+        self.anchor.min(self.scroll)..=self.anchor.max(self.scroll)
+    }
 
-impl Default for Command {
-    fn default() -> Self {
-        Self {
-            cursor: 0,
-            query: Vec::new(),
+    /// Query characters covered by the `v` selection.
+    pub fn selection(&self) -> Range<usize> {
+        let start = self.query_anchor.min(self.cursor);
+        let end = self.query_anchor.max(self.cursor) + 1;
+        start.min(self.query.len())..end.min(self.query.len())
+    }
+
+    /// Marked results, or the one under the cursor when nothing is marked. Context lines
+    /// are never selected.
+    pub fn selected(&self) -> Vec<&Match> {
+        let marked = self
+            .result
+            .iter()
+            .filter(|result| result.marked && !result.context)
+            .collect::<Vec<_>>();
+        if !marked.is_empty() {
+            return marked;
         }
+
+        self.result
+            .get(self.scroll)
+            .filter(|result| !result.context)
+            .into_iter()
+            .collect()
     }
+
+    /// Whether results are ranked by fuzzy score rather than kept in ripgrep's order.
+    pub fn is_fuzzy(&self) -> bool {
+        self.source != Source::Content || self.matcher == Matcher::Fuzzy
+    }
+}
+
+/// Feedback shown in the footer until the next key press.
+#[derive(Debug, Clone)]
+pub enum Message {
+    Info(String),
+    Error(String),
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Command {
+    pub cursor: usize,
+    pub query: Vec<char>,
+    #[serde(skip)]
+    pub completion: Option<Completion>,
+    /// Where to go back to once the command line closes.
+    #[serde(skip)]
+    pub return_to: Window,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct App {
-    pub vi_command: String,
     pub running: bool,
     pub window: Window,
     pub command: Command,
     pub search: Search,
     pub args: Vec<String>,
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    /// The highlighted row of the options panel.
+    #[serde(skip)]
+    pub option: usize,
+    #[serde(skip)]
+    pub pending: Pending,
+    #[serde(skip)]
+    pub history: History,
+    #[serde(default)]
+    pub registers: Registers,
+    #[serde(skip)]
+    pub review: Option<Review>,
+    #[serde(skip)]
+    pub edit: Option<Edit>,
+    #[serde(skip)]
+    pub search_history: InputHistory,
+    #[serde(skip)]
+    pub command_history: InputHistory,
+    #[serde(skip)]
+    pub journal: Journal,
+    #[serde(skip)]
+    pub worker: Worker,
+    /// Candidate lines read from stdin in `--filter` mode.
+    #[serde(skip)]
+    pub input: Arc<Vec<String>>,
+    /// Lines picked in `--filter` mode, `None` until the user accepts or gives up.
+    #[serde(skip)]
+    pub output: Option<Vec<String>>,
+    #[serde(skip)]
+    pub message: Option<Message>,
+    #[serde(skip)]
+    pub config: Config,
+    /// Address of a running Neovim to send jumps to instead of spawning an editor.
+    #[serde(skip)]
+    pub server: Option<String>,
 }
 
 impl Default for App {
     fn default() -> Self {
         Self {
             running: true,
-            vi_command: String::new(),
             window: Window::Search,
             command: Command::default(),
             search: Search::default(),
             args: Vec::new(),
+            paths: Vec::new(),
+            option: 0,
+            pending: Pending::default(),
+            history: History::default(),
+            registers: Registers::default(),
+            review: None,
+            edit: None,
+            search_history: InputHistory::default(),
+            command_history: InputHistory::default(),
+            journal: Journal::default(),
+            worker: Worker::default(),
+            input: Arc::default(),
+            output: None,
+            message: None,
+            config: Config::default(),
+            server: None,
         }
     }
 }
 
+/// `~/.config/lens`, where the session and the user configuration live.
+pub fn config_dir() -> anyhow::Result<PathBuf> {
+    let home = env::var("HOME")?;
+    Ok(PathBuf::from(format!("{}/.config/lens", home)))
+}
+
 impl App {
     pub fn new() -> anyhow::Result<Self> {
-        let home = env::var("HOME")?;
-        let config = PathBuf::from(format!("{}/.config/lens", home));
-        let session = fs::read_to_string(config.join("session.json"))?;
+        Self::restore(&fs::read_to_string(config_dir()?.join("session.json"))?)
+    }
 
-        Ok(serde_json::from_str(&session)?)
+    /// The app as `session` left it. A filter session's lines came from a stdin that's
+    /// gone now, so one saved by an older version starts over instead.
+    fn restore(session: &str) -> anyhow::Result<Self> {
+        let mut app = serde_json::from_str::<Self>(session)?;
+        if app.search.source == Source::Stdin {
+            app.search = Search::default();
+        }
+
+        Ok(app)
+    }
+
+    /// Returns whether an ambiguous key sequence has waited longer than the configured
+    /// timeout and should be resolved with what was typed so far.
+    pub fn tick(&self) -> bool {
+        self.pending
+            .expired(Duration::from_millis(self.config.timeout))
     }
 
-    pub fn tick(&self) {}
+    /// Which set of key mappings applies right now.
+    pub fn context(&self) -> Context {
+        match (&self.window, &self.search.mode) {
+            (Window::Command | Window::History, _) => Context::Command,
+            (Window::Options | Window::Review | Window::Journal, _) => Context::Normal,
+            _ if self.pending.operator.is_some() => Context::Operator,
+            (_, Mode::Insert | Mode::Replace) => Context::Insert,
+            (_, Mode::Visual | Mode::VisualLine) => Context::Visual,
+            (_, Mode::Normal) => Context::Normal,
+        }
+    }
 
     pub fn quit(&mut self) {
         self.running = false;
     }
 
+    /// Saves the session for the next launch. A `--filter` session isn't saved, since
+    /// there'd be no input to come back to.
     pub fn save(&self) -> anyhow::Result<()> {
-        let home = env::var("HOME")?;
-        let config = PathBuf::from(format!("{}/.config/lens", home));
-        let _ = fs::create_dir(&config);
-        fs::write(config.join("session.json"), serde_json::to_string(&self)?)?;
+        let Some(session) = self.session()? else {
+            return Ok(());
+        };
+        let config = config_dir()?;
+        let _ = fs::create_dir_all(&config);
+        fs::write(config.join("session.json"), session)?;
 
         Ok(())
     }
 
+    fn session(&self) -> anyhow::Result<Option<String>> {
+        if self.search.source == Source::Stdin {
+            return Ok(None);
+        }
+        let mut session = serde_json::to_value(self)?;
+        if !self.config.persist_registers {
+            if let Some(session) = session.as_object_mut() {
+                session.remove("registers");
+            }
+        }
+
+        Ok(Some(serde_json::to_string(&session)?))
+    }
+
     pub fn delete_session(&self) -> anyhow::Result<()> {
-        let home = env::var("HOME")?;
-        let config = PathBuf::from(format!("{}/.config/lens", home));
-        let _ = fs::remove_file(config.join("session.json"));
+        let _ = fs::remove_file(config_dir()?.join("session.json"));
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(source: Source) -> App {
+        let mut app = App::default();
+        app.search.source = source;
+        app.search.query = "foo".chars().collect();
+        app
+    }
+
+    #[test]
+    fn restores_a_saved_session() {
+        let session = search(Source::Files).session().unwrap().expect("saved");
+        let app = App::restore(&session).unwrap();
+        assert_eq!(app.search.source, Source::Files);
+        assert_eq!(app.search.query, ['f', 'o', 'o']);
+    }
+
+    #[test]
+    fn never_saves_or_restores_a_filter_session() {
+        assert!(search(Source::Stdin).session().unwrap().is_none());
+
+        let mut session = serde_json::to_value(search(Source::Content)).unwrap();
+        session["search"]["source"] = "Stdin".into();
+        let app = App::restore(&session.to_string()).unwrap();
+        assert_eq!(app.search.source, Source::Content);
+        assert!(app.search.query.is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::app::{App, Matcher, Mode, Source};
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, IsTerminal, Read},
+    iter,
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Cross-platform vim-like command line fuzzy finder.
+#[derive(Debug, Parser)]
+#[command(name = "lens", version, about, args_override_self = true)]
+pub struct Cli {
+    /// Files or directories to search [default: current directory]
+    #[arg(value_name = "PATH")]
+    pub paths: Vec<PathBuf>,
+
+    /// Include or exclude files and directories matching GLOB (may be repeated)
+    #[arg(short, long, value_name = "GLOB")]
+    pub glob: Vec<String>,
+
+    /// Only search files of the given ripgrep TYPE (may be repeated)
+    #[arg(short = 't', long = "type", value_name = "TYPE")]
+    pub file_type: Vec<String>,
+
+    /// Search hidden files and directories
+    #[arg(long)]
+    pub hidden: bool,
+
+    /// Don't respect .gitignore, .ignore and other ignore files
+    #[arg(long)]
+    pub no_ignore: bool,
+
+    /// Rank results with the fuzzy matcher instead of treating the query as a regex
+    #[arg(short, long)]
+    pub fuzzy: bool,
+
+    /// Find files by name instead of searching their contents
+    #[arg(long)]
+    pub files: bool,
+
+    /// Filter lines read from stdin and print the selection to stdout. Exits with 0 when
+    /// a line was selected, 1 when nothing matched and 130 when aborted
+    #[arg(long, conflicts_with = "files")]
+    pub filter: bool,
+
+    /// Open results in the Neovim listening on ADDR (a socket path or host:port) instead
+    /// of spawning an editor [default: $NVIM]
+    #[arg(long, value_name = "ADDR")]
+    pub server: Option<String>,
+
+    /// Start with QUERY already typed into the search box
+    #[arg(short, long, value_name = "QUERY")]
+    pub query: Option<String>,
+
+    /// Extra arguments passed verbatim to ripgrep
+    #[arg(last = true, value_name = "RG_ARGS")]
+    pub rg_args: Vec<String>,
+}
+
+impl Cli {
+    /// Parses `LENS_DEFAULT_OPTS` followed by the process arguments, exiting with a usage
+    /// error when they are invalid. Later flags win, so the command line overrides the
+    /// environment.
+    pub fn parse_validated() -> Self {
+        let mut args = env::args_os();
+        let program = args.next().unwrap_or_else(|| "lens".into());
+        let defaults = env::var("LENS_DEFAULT_OPTS").unwrap_or_default();
+        let cli = Self::parse_from(
+            iter::once(program)
+                .chain(split_words(&defaults).into_iter().map(OsString::from))
+                .chain(args),
+        );
+
+        if cli.filter && io::stdin().is_terminal() {
+            Self::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "--filter reads candidates from stdin, pipe something into lens",
+                )
+                .exit();
+        }
+
+        if let Some(path) = cli.paths.iter().find(|path| !path.exists()) {
+            Self::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("path '{}' does not exist", path.display()),
+                )
+                .exit();
+        }
+
+        cli
+    }
+
+    /// Arguments forwarded to every `rg` invocation.
+    pub fn rg_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        self.glob.iter().for_each(|glob| {
+            args.push("--glob".to_string());
+            args.push(glob.clone());
+        });
+        self.file_type.iter().for_each(|file_type| {
+            args.push("--type".to_string());
+            args.push(file_type.clone());
+        });
+        args.extend(self.rg_args.iter().cloned());
+
+        args
+    }
+
+    pub fn apply(self, app: &mut App) -> anyhow::Result<()> {
+        app.args = app.config.rg_args.clone();
+        app.args.extend(self.rg_args());
+        // These go through the search options so `:set` sees them and can turn them off.
+        if self.hidden {
+            app.config.search.hidden = true;
+        }
+        if self.no_ignore {
+            app.config.search.ignore = false;
+        }
+        app.paths = self.paths;
+        app.server = self
+            .server
+            .or_else(|| env::var("NVIM").ok().filter(|nvim| !nvim.is_empty()));
+        if self.fuzzy {
+            app.search.matcher = Matcher::Fuzzy;
+        }
+        if self.files {
+            app.search.source = Source::Files;
+        }
+        if self.filter {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            app.input = Arc::new(
+                String::from_utf8_lossy(&input)
+                    .lines()
+                    .map(|line| line.to_string())
+                    .collect(),
+            );
+            app.search.source = Source::Stdin;
+            app.search.mode = Mode::Insert;
+        }
+
+        if let Some(query) = self.query {
+            app.search.query = query.chars().collect();
+            app.search.cursor = match app.search.mode {
+                Mode::Insert => app.search.query.len(),
+                _ => app.search.query.len().saturating_sub(1),
+            };
+            app.search.scroll = 0;
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits `text` on whitespace, keeping single- or double-quoted runs together.
+pub(crate) fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut quote = None;
+
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(
+            split_words("  --hidden\t-g '*.rs' "),
+            ["--hidden", "-g", "*.rs"]
+        );
+        assert!(split_words("   ").is_empty());
+    }
+
+    #[test]
+    fn keeps_quoted_runs_together() {
+        assert_eq!(
+            split_words(r#"--query "fn main" -g 'a b'c"#),
+            ["--query", "fn main", "-g", "a bc"]
+        );
+        // Each quote type may hold the other, and an empty pair is still a word.
+        assert_eq!(split_words(r#""it's" '' x"#), ["it's", "", "x"]);
+    }
+
+    #[test]
+    fn defaults_come_before_the_command_line() {
+        let defaults = split_words("--hidden --query old");
+        let cli = Cli::parse_from(
+            std::iter::once("lens".to_string())
+                .chain(defaults)
+                .chain(["--query".to_string(), "new".to_string()]),
+        );
+        assert!(cli.hidden);
+        assert_eq!(cli.query.as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn hidden_and_no_ignore_become_search_options() {
+        let mut app = App::default();
+        let cli = Cli::parse_from(["lens", "--hidden", "--no-ignore", "-g", "*.rs"]);
+        cli.apply(&mut app).unwrap();
+        assert!(app.config.search.hidden);
+        assert!(!app.config.search.ignore);
+        assert_eq!(app.args, ["--glob", "*.rs"]);
+    }
+}
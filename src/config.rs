@@ -0,0 +1,441 @@
+use crate::{
+    app::config_dir,
+    editor::Editors,
+    keymap::{parse_keys, Keymap},
+    options::{SearchOptions, Value},
+};
+use ratatui::style::Color;
+use serde::{de::Error, Deserialize, Deserializer};
+use std::{env, fs, io, path::PathBuf, str::FromStr};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layout {
+    /// Width of the search and results column, in percent.
+    pub results: u16,
+    /// Width of the preview column, in percent.
+    pub preview: u16,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            results: 50,
+            preview: 35,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    #[serde(deserialize_with = "color")]
+    pub background: Color,
+    #[serde(deserialize_with = "color")]
+    pub border: Color,
+    #[serde(deserialize_with = "color")]
+    pub focus: Color,
+    #[serde(deserialize_with = "color")]
+    pub text: Color,
+    #[serde(deserialize_with = "color")]
+    pub muted: Color,
+    #[serde(deserialize_with = "color")]
+    pub query: Color,
+    #[serde(deserialize_with = "color")]
+    pub prompt: Color,
+    #[serde(deserialize_with = "color")]
+    pub matched: Color,
+    #[serde(deserialize_with = "color")]
+    pub selection: Color,
+    #[serde(deserialize_with = "color")]
+    pub error: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: Color::Black,
+            border: Color::Blue,
+            focus: Color::LightBlue,
+            text: Color::White,
+            muted: Color::DarkGray,
+            query: Color::LightRed,
+            prompt: Color::Magenta,
+            matched: Color::LightRed,
+            selection: Color::Yellow,
+            error: Color::LightRed,
+        }
+    }
+}
+
+/// Accepts anything ratatui can parse: names like `lightblue`, indices and `#rrggbb`.
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let color = String::deserialize(deserializer)?;
+    Color::from_str(&color).map_err(|_| D::Error::custom(format!("invalid color '{}'", color)))
+}
+
+/// Settings read from `~/.config/lens/config.toml` and the nearest `.lens.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Arguments passed to every `rg` invocation, before the command-line ones.
+    pub rg_args: Vec<String>,
+    pub editors: Editors,
+    pub layout: Layout,
+    /// Milliseconds between ticks.
+    pub tick_rate: u64,
+    /// Lines shown above the hit in the preview; the window is twice as tall.
+    pub preview_context: usize,
+    pub theme: Theme,
+    /// Defaults for the options `:set` changes.
+    pub search: SearchOptions,
+    /// Milliseconds to wait for the rest of a multi-key command before giving up on it.
+    pub timeout: u64,
+    /// Whether yank registers are saved with the session.
+    pub persist_registers: bool,
+    /// How many past queries and commands to remember; 0 turns the history off.
+    pub history_size: usize,
+    /// What `<leader>` stands for in `keys`.
+    pub leader: String,
+    /// Mappings such as `nnoremap <leader>f toggle-matcher`, applied in order.
+    pub keys: Vec<String>,
+    #[serde(skip)]
+    pub keymap: Keymap,
+    /// Settings changed with `:set` or the options panel since the last `:w`.
+    #[serde(skip)]
+    pub changed: Vec<&'static str>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rg_args: Vec::new(),
+            editors: Editors::default(),
+            layout: Layout::default(),
+            tick_rate: 250,
+            preview_context: 25,
+            theme: Theme::default(),
+            search: SearchOptions::default(),
+            timeout: 1000,
+            persist_registers: true,
+            history_size: 1000,
+            leader: "\\".to_string(),
+            keys: Vec::new(),
+            keymap: Keymap::default(),
+            changed: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> anyhow::Result<PathBuf> {
+        Ok(config_dir()?.join("config.toml"))
+    }
+
+    /// The closest `.lens.toml` in the current directory or one of its parents.
+    pub fn project_path() -> Option<PathBuf> {
+        let cwd = env::current_dir().ok()?;
+        cwd.ancestors()
+            .map(|dir| dir.join(".lens.toml"))
+            .find(|path| path.is_file())
+    }
+
+    /// Loads the user config with the project config layered on top. Anything that can't
+    /// be read or doesn't validate is reported and replaced by the defaults.
+    pub fn load() -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut table = toml::Table::new();
+
+        let paths = Self::path().ok().into_iter().chain(Self::project_path());
+        for path in paths.filter(|path| path.is_file()) {
+            match fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| Ok(text.parse::<toml::Table>()?))
+            {
+                Ok(layer) => merge(&mut table, layer),
+                Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+
+        let (config, invalid) = Self::from_table(table);
+        errors.extend(invalid);
+        (config, errors)
+    }
+
+    /// Builds the config from the merged layers, the defaults standing in for whatever
+    /// doesn't deserialize or validate.
+    fn from_table(table: toml::Table) -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut config = match Config::deserialize(toml::Value::Table(table)) {
+            Ok(config) => config,
+            Err(e) => {
+                errors.push(format!("config: {}", e.message()));
+                return (Self::default(), errors);
+            }
+        };
+
+        // User templates are layered over the shipped ones rather than replacing them.
+        let mut editors = Editors::default();
+        editors.extend(config.editors);
+        config.editors = editors;
+
+        errors.extend(config.validate());
+        errors.extend(config.map_keys());
+        (config, errors)
+    }
+
+    /// Resets invalid settings to their defaults, returning what was wrong.
+    pub(crate) fn validate(&mut self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let defaults = Self::default();
+
+        let Layout { results, preview } = self.layout;
+        if results == 0 || preview == 0 || results + preview > 100 {
+            errors.push(format!(
+                "config: layout widths must be positive and add up to at most 100, got {} and {}",
+                results, preview
+            ));
+            self.layout = defaults.layout;
+        }
+        if self.tick_rate == 0 {
+            errors.push("config: tick_rate must be at least 1 millisecond".to_string());
+            self.tick_rate = defaults.tick_rate;
+        }
+        if self.timeout == 0 {
+            errors.push("config: timeout must be at least 1 millisecond".to_string());
+            self.timeout = defaults.timeout;
+        }
+        if self.preview_context == 0 {
+            errors.push("config: preview_context must be at least 1 line".to_string());
+            self.preview_context = defaults.preview_context;
+        }
+
+        errors
+    }
+
+    /// The setting `name` as `:set` and the options panel know it.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        Some(match name {
+            "results-width" => Value::Number(self.layout.results.into()),
+            "preview-width" => Value::Number(self.layout.preview.into()),
+            "preview-context" => Value::Number(self.preview_context as u64),
+            "timeout" => Value::Number(self.timeout),
+            "persist-registers" => Value::Bool(self.persist_registers),
+            name => return self.search.get(name),
+        })
+    }
+
+    /// Sets `name`, or returns `None` if there's no such setting or `value` doesn't fit
+    /// it.
+    pub fn set(&mut self, name: &str, value: Value) -> Option<()> {
+        match (name, value) {
+            ("results-width", Value::Number(value)) => {
+                self.layout.results = value.try_into().ok()?
+            }
+            ("preview-width", Value::Number(value)) => {
+                self.layout.preview = value.try_into().ok()?
+            }
+            ("preview-context", Value::Number(value)) => {
+                self.preview_context = value.try_into().ok()?
+            }
+            ("timeout", Value::Number(value)) => self.timeout = value,
+            ("persist-registers", Value::Bool(value)) => self.persist_registers = value,
+            (name, value) => return self.search.set(name, value),
+        }
+
+        Some(())
+    }
+
+    /// Writes the settings changed at runtime into the user config, leaving everything
+    /// else in the file, comments included, as it was. Returns how many were written.
+    pub fn persist(&mut self) -> anyhow::Result<usize> {
+        let path = Self::path()?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut document = text.parse::<toml_edit::DocumentMut>()?;
+        self.update(&mut document)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, document.to_string())?;
+
+        Ok(std::mem::take(&mut self.changed).len())
+    }
+
+    /// Sets the changed settings in `document`, failing if a table they belong in is
+    /// something else.
+    fn update(&self, document: &mut toml_edit::DocumentMut) -> anyhow::Result<()> {
+        for &name in &self.changed {
+            let value = match self.get(name) {
+                Some(Value::Bool(value)) => toml_edit::value(value),
+                Some(Value::Number(value)) => toml_edit::value(value as i64),
+                Some(Value::Text(value)) => toml_edit::value(value),
+                None => continue,
+            };
+            let (table, key) = match name {
+                "results-width" => (Some("layout"), "results".to_string()),
+                "preview-width" => (Some("layout"), "preview".to_string()),
+                "preview-context" | "timeout" | "persist-registers" => {
+                    (None, name.replace('-', "_"))
+                }
+                name => (Some("search"), name.replace('-', "_")),
+            };
+            let item = match table {
+                Some(table) => document
+                    .entry(table)
+                    .or_insert(toml_edit::table())
+                    .as_table_like_mut()
+                    .ok_or_else(|| anyhow::anyhow!("{} is not a table", table))?
+                    .entry(&key)
+                    .or_insert(toml_edit::Item::None),
+                None => &mut document[&key],
+            };
+            // Keep any comment after a value that's being replaced.
+            let decor = item.as_value().map(|old| old.decor().clone());
+            *item = value;
+            if let (Some(decor), Some(value)) = (decor, item.as_value_mut()) {
+                *value.decor_mut() = decor;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Layers the `keys` entries over the default bindings, skipping the broken ones.
+    fn map_keys(&mut self) -> Vec<String> {
+        let leader = match parse_keys(&self.leader, &[]) {
+            Ok(leader) => leader,
+            Err(e) => {
+                self.leader = Self::default().leader;
+                return vec![format!("config: leader: {}", e)];
+            }
+        };
+
+        self.keys
+            .iter()
+            .filter_map(|entry| self.keymap.apply(entry, &leader).err())
+            .map(|e| format!("config: keys: {}", e))
+            .collect()
+    }
+}
+
+/// Deep-merges `layer` into `base`, with `layer` winning on conflicts.
+fn merge(base: &mut toml::Table, layer: toml::Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(layer)) => merge(base, layer),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::{Action, Context, Lookup};
+
+    fn table(text: &str) -> toml::Table {
+        text.parse().expect("parses")
+    }
+
+    #[test]
+    fn merges_layers_deeply() {
+        let mut base = table("tick_rate = 100\n[layout]\nresults = 40\npreview = 60\n");
+        merge(&mut base, table("timeout = 5\n[layout]\nresults = 30\n"));
+        assert_eq!(
+            base,
+            table("tick_rate = 100\ntimeout = 5\n[layout]\nresults = 30\npreview = 60\n")
+        );
+    }
+
+    #[test]
+    fn later_layers_replace_non_tables() {
+        let mut base = table("rg_args = [\"--hidden\"]\nleader = \",\"\n");
+        merge(&mut base, table("rg_args = [\"--follow\"]\n"));
+        assert_eq!(base, table("rg_args = [\"--follow\"]\nleader = \",\"\n"));
+    }
+
+    #[test]
+    fn reads_settings_over_the_defaults() {
+        let (config, errors) = Config::from_table(table(
+            "timeout = 300\n[layout]\nresults = 30\npreview = 70\n[search]\nhidden = true\nfixed_strings = true\n",
+        ));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.timeout, 300);
+        assert_eq!((config.layout.results, config.layout.preview), (30, 70));
+        assert!(config.search.hidden && config.search.fixed_strings);
+        assert_eq!(config.tick_rate, Config::default().tick_rate);
+    }
+
+    #[test]
+    fn falls_back_to_the_defaults_on_unknown_keys() {
+        let (config, errors) = Config::from_table(table("timeout = 300\nbogus = 1\n"));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("bogus"), "{}", errors[0]);
+        assert_eq!(config.timeout, Config::default().timeout);
+    }
+
+    #[test]
+    fn resets_only_the_invalid_settings() {
+        let (config, errors) = Config::from_table(table(
+            "timeout = 0\ntick_rate = 50\n[layout]\nresults = 80\npreview = 80\n",
+        ));
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(config.timeout, Config::default().timeout);
+        assert_eq!(config.layout.results, Layout::default().results);
+        assert_eq!(config.tick_rate, 50);
+    }
+
+    #[test]
+    fn reports_broken_mappings_and_keeps_the_rest() {
+        let (config, errors) = Config::from_table(table(
+            "keys = [\"nnoremap <bogus> accept\", \"nnoremap Q mark-all\"]\n",
+        ));
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].starts_with("config: keys:"), "{}", errors[0]);
+        let keys = parse_keys("Q", &[]).unwrap();
+        assert!(matches!(
+            config.keymap.lookup(Context::Normal, &keys),
+            Lookup::Action(Action::MarkAll)
+        ));
+    }
+
+    #[test]
+    fn updates_settings_in_place() {
+        let mut config = Config::default();
+        config.set("hidden", Value::Bool(true));
+        config.set("results-width", Value::Number(30));
+        config.set("timeout", Value::Number(300));
+        config.changed = vec!["hidden", "results-width", "timeout"];
+
+        let mut document = "timeout = 5 # ms\nlayout = { preview = 70 }\n"
+            .parse::<toml_edit::DocumentMut>()
+            .unwrap();
+        config.update(&mut document).unwrap();
+        assert_eq!(
+            table(&document.to_string()),
+            table(
+                "timeout = 300\nlayout = { preview = 70, results = 30 }\n[search]\nhidden = true\n"
+            )
+        );
+        assert!(document.to_string().contains("timeout = 300 # ms"));
+    }
+
+    #[test]
+    fn refuses_to_update_a_non_table() {
+        let mut config = Config::default();
+        config.set("hidden", Value::Bool(true));
+        config.changed = vec!["hidden"];
+
+        let mut document = "search = 1\n".parse::<toml_edit::DocumentMut>().unwrap();
+        let error = config.update(&mut document).unwrap_err();
+        assert_eq!(error.to_string(), "search is not a table");
+    }
+}
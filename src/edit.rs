@@ -0,0 +1,53 @@
+use crate::{
+    app::Match,
+    patch::{FilePatch, Hunk},
+};
+use std::{collections::BTreeMap, path::PathBuf, time::SystemTime};
+
+/// Results being edited in place, wgrep style. The highlighted result is edited through
+/// the query line, with the query itself put aside until editing ends.
+#[derive(Debug)]
+pub struct Edit {
+    pub query: Vec<char>,
+    pub cursor: usize,
+    /// The result loaded into the query line.
+    pub row: usize,
+    /// The original text of every result changed so far, by row.
+    pub originals: BTreeMap<usize, String>,
+    /// When the results were searched for. Files modified after that aren't written.
+    pub since: Option<SystemTime>,
+}
+
+impl Edit {
+    /// Whether any result reads differently than it did.
+    pub fn is_modified(&self, results: &[Match]) -> bool {
+        self.originals
+            .iter()
+            .any(|(&row, original)| results.get(row).is_some_and(|r| &r.text != original))
+    }
+
+    /// Every changed result as a hunk, one patch per file.
+    pub fn patches(&self, results: &[Match]) -> Vec<FilePatch> {
+        let mut files = BTreeMap::<&PathBuf, Vec<Hunk>>::new();
+        for (&row, original) in &self.originals {
+            let Some(result) = results.get(row).filter(|r| &r.text != original) else {
+                continue;
+            };
+            files.entry(&result.path).or_default().push(Hunk {
+                line: result.line,
+                before: original.clone(),
+                after: result.text.clone(),
+                accepted: true,
+            });
+        }
+
+        files
+            .into_iter()
+            .map(|(path, hunks)| FilePatch {
+                path: path.clone(),
+                hunks,
+                unchanged_since: self.since,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, env, path::Path, process::Command};
+
+/// Argument templates for opening a location, keyed by the editor's program name.
+///
+/// Every argument may use the `{file}`, `{line}` and `{column}` placeholders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Editors(HashMap<String, Vec<String>>);
+
+impl Default for Editors {
+    fn default() -> Self {
+        let templates: [(&[&str], &[&str]); 9] = [
+            (
+                &["vi", "vim", "nvim"],
+                &["+normal {line}G{column}|", "{file}"],
+            ),
+            (&["emacs", "emacsclient"], &["+{line}:{column}", "{file}"]),
+            (&["hx", "helix"], &["{file}:{line}:{column}"]),
+            (&["kak"], &["+{line}:{column}", "{file}"]),
+            (&["nano"], &["+{line},{column}", "{file}"]),
+            (&["micro"], &["+{line}:{column}", "{file}"]),
+            (
+                &["code", "code-insiders", "codium"],
+                &["--goto", "{file}:{line}:{column}"],
+            ),
+            (&["subl"], &["{file}:{line}:{column}"]),
+            (&["less"], &["+{line}", "{file}"]),
+        ];
+
+        Self(
+            templates
+                .into_iter()
+                .flat_map(|(names, args)| {
+                    names.iter().map(move |name| {
+                        (
+                            name.to_string(),
+                            args.iter().map(|a| a.to_string()).collect(),
+                        )
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Editors {
+    /// Adds `overrides` on top of the current templates.
+    pub fn extend(&mut self, overrides: Editors) {
+        self.0.extend(overrides.0);
+    }
+
+    fn template(&self, name: &str) -> Vec<String> {
+        self.0
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| vec!["{file}".to_string()])
+    }
+}
+
+/// The user's editor as configured through `$VISUAL` or `$EDITOR`.
+#[derive(Debug)]
+pub struct Editor {
+    program: String,
+    args: Vec<String>,
+    pub name: String,
+}
+
+impl Editor {
+    /// Reads `$VISUAL`, falling back to `$EDITOR`. Extra words such as `code --wait` are
+    /// kept and passed before the location.
+    pub fn from_env() -> Option<Self> {
+        let value = ["VISUAL", "EDITOR"]
+            .into_iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.trim().is_empty())?;
+        let mut words = value.split_whitespace().map(|word| word.to_string());
+        let program = words.next()?;
+        let name = Path::new(&program)
+            .file_name()?
+            .to_string_lossy()
+            .to_string();
+
+        Some(Self {
+            args: words.collect(),
+            program,
+            name,
+        })
+    }
+
+    /// A bare command for the editor, without any location.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+
+    /// A command opening `file` at `line` and `column` using the matching template.
+    pub fn open(&self, editors: &Editors, file: &Path, line: usize, column: usize) -> Command {
+        let mut command = self.command();
+        command.args(editors.template(&self.name).iter().map(|arg| {
+            arg.replace("{file}", &file.to_string_lossy())
+                .replace("{line}", &line.to_string())
+                .replace("{column}", &column.to_string())
+        }));
+        command
+    }
+}
@@ -1,16 +1,23 @@
-use crate::app::AppResult;
+use crate::app::{AppResult, Match};
 use crossterm::event::{self, Event as CrosstermEvent, KeyEvent, MouseEvent};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::{Duration, Instant};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum Event {
     Tick,
     Key(KeyEvent),
     Mouse(MouseEvent),
     Resize(u16, u16),
+    Search {
+        id: u64,
+        results: Vec<Match>,
+        /// Hits left out because their path or line isn't valid UTF-8.
+        skipped: usize,
+        done: bool,
+    },
 }
 
 #[allow(dead_code)]
@@ -68,6 +75,10 @@ impl EventHandler {
         }
     }
 
+    pub fn sender(&self) -> mpsc::Sender<Event> {
+        self.sender.clone()
+    }
+
     pub fn next(&self) -> AppResult<Event> {
         Ok(self.receiver.recv()?)
     }
@@ -0,0 +1,249 @@
+use crate::{cli::split_words, options};
+use std::{fs, path::Path};
+
+/// What an ex command takes after its name, which decides how Tab completes it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Argument {
+    None,
+    /// An optional file name.
+    Path,
+    /// Option names, as for `:set`.
+    Option,
+    /// `/pattern/replacement/`, taken as it is.
+    Pattern,
+}
+
+/// Every ex command, by full name. Any unambiguous prefix works too, as in vim.
+pub const COMMANDS: [(&str, Argument); 14] = [
+    ("cexport", Argument::Path),
+    ("copen", Argument::None),
+    ("edit", Argument::None),
+    ("journal", Argument::None),
+    ("options", Argument::None),
+    ("q", Argument::None),
+    ("quit", Argument::None),
+    ("s", Argument::Pattern),
+    ("set", Argument::Option),
+    ("undo-files", Argument::None),
+    ("w", Argument::None),
+    ("wq", Argument::None),
+    ("write", Argument::None),
+    ("x", Argument::None),
+];
+
+/// Tab cycling through the completions of one word on the command line.
+#[derive(Debug, Default)]
+pub struct Completion {
+    /// Character position of the word being completed.
+    pub start: usize,
+    pub candidates: Vec<String>,
+    pub index: usize,
+}
+
+/// A parsed command line such as `:cexport! out.err`.
+#[derive(Debug, PartialEq)]
+pub struct Ex {
+    /// The full command name, even when an abbreviation was typed.
+    pub name: &'static str,
+    pub bang: bool,
+    /// A `%` before the name, making it act on every result rather than the marked ones.
+    pub all: bool,
+    /// Everything after the name, untouched.
+    pub raw: String,
+    /// `raw` split into words, keeping quoted runs together.
+    pub args: Vec<String>,
+}
+
+/// Splits a command line into the name as typed, and the rest.
+fn split(line: &str) -> (&str, &str) {
+    let line = line.trim_start_matches(':').trim_start();
+    let line = line.strip_prefix('%').unwrap_or(line);
+    let end = line
+        .find(|c: char| !c.is_alphanumeric() && c != '-')
+        .unwrap_or(line.len());
+
+    line.split_at(end)
+}
+
+/// The command `name` stands for: an exact name, or else the only one it's a prefix of.
+fn resolve(name: &str) -> Result<(&'static str, Argument), String> {
+    if let Some(&command) = COMMANDS.iter().find(|(full, _)| *full == name) {
+        return Ok(command);
+    }
+
+    let mut candidates = COMMANDS.iter().filter(|(full, _)| full.starts_with(name));
+    match (candidates.next(), candidates.next()) {
+        (Some(&command), None) => Ok(command),
+        (Some(_), Some(_)) => Err(format!("ambiguous command: {}", name)),
+        _ => Err(format!("not an editor command: {}", name)),
+    }
+}
+
+/// Parses `line`, with or without its leading `:`.
+pub fn parse(line: &str) -> Result<Ex, String> {
+    let (name, rest) = split(line);
+    if name.is_empty() {
+        return Err(format!(
+            "not an editor command: {}",
+            line.trim_start_matches(':')
+        ));
+    }
+
+    let (name, argument) = resolve(name)?;
+    let all = line.trim_start_matches(':').trim_start().starts_with('%');
+    let (bang, raw) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest.trim_start().to_string()),
+        None => (false, rest.trim_start().to_string()),
+    };
+    let args = split_words(&raw);
+    if argument == Argument::None && !args.is_empty() {
+        return Err(format!("trailing characters: {}", raw));
+    }
+
+    Ok(Ex {
+        name,
+        bang,
+        all,
+        raw,
+        args,
+    })
+}
+
+/// Completions for the word ending `line`, which is the command line up to the cursor,
+/// along with the character position that word starts at.
+pub fn complete(line: &str) -> (usize, Vec<String>) {
+    let (name, rest) = split(line);
+    let start = line.chars().count() - rest.chars().count();
+    if rest.is_empty() {
+        let names = COMMANDS
+            .iter()
+            .map(|(full, _)| full.to_string())
+            .filter(|full| full.starts_with(name))
+            .collect::<Vec<_>>();
+        return (start - name.chars().count(), names);
+    }
+
+    let argument = resolve(name).map_or(Argument::None, |(_, argument)| argument);
+    let word = rest.rsplit(char::is_whitespace).next().unwrap_or_default();
+    let start = line.chars().count() - word.chars().count();
+    match argument {
+        Argument::Path => (start, complete_path(word)),
+        Argument::Option => (start, options::complete(word)),
+        Argument::None | Argument::Pattern => (start, Vec::new()),
+    }
+}
+
+/// Files and directories starting with `word`, with a `/` after directories. Hidden ones
+/// only show up once a `.` is typed.
+fn complete_path(word: &str) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(index) => word.split_at(index + 1),
+        None => ("", word),
+    };
+    let Ok(entries) = fs::read_dir(match dir {
+        "" => Path::new("."),
+        dir => Path::new(dir),
+    }) else {
+        return Vec::new();
+    };
+
+    let mut paths = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn parses_names_bangs_and_arguments() {
+        let ex = parse(":cexport! 'out file.err'").unwrap();
+        assert_eq!(ex.name, "cexport");
+        assert!(ex.bang);
+        assert!(!ex.all);
+        assert_eq!(ex.raw, "'out file.err'");
+        assert_eq!(ex.args, ["out file.err"]);
+
+        let ex = parse("%s/foo/bar/").unwrap();
+        assert_eq!((ex.name, ex.all), ("s", true));
+        assert_eq!(ex.raw, "/foo/bar/");
+        assert_eq!(
+            parse("set nohidden context=2").unwrap().args,
+            ["nohidden", "context=2"]
+        );
+    }
+
+    #[test]
+    fn resolves_abbreviations() {
+        assert_eq!(parse("cex").unwrap().name, "cexport");
+        assert_eq!(parse("j").unwrap().name, "journal");
+        assert_eq!(parse("undo").unwrap().name, "undo-files");
+        assert_eq!(parse("wr").unwrap().name, "write");
+        // Exact names win over longer commands they're a prefix of.
+        assert_eq!(parse("q").unwrap().name, "q");
+        assert_eq!(parse("w").unwrap().name, "w");
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert_eq!(parse("c").unwrap_err(), "ambiguous command: c");
+        assert_eq!(parse("frob").unwrap_err(), "not an editor command: frob");
+        assert_eq!(parse(":").unwrap_err(), "not an editor command: ");
+        assert_eq!(parse("/x").unwrap_err(), "not an editor command: /x");
+        assert_eq!(parse("quit now").unwrap_err(), "trailing characters: now");
+    }
+
+    #[test]
+    fn completes_command_names() {
+        assert_eq!(complete("c"), (0, vec!["cexport".into(), "copen".into()]));
+        assert_eq!(complete(":%wr"), (2, vec!["write".into()]));
+        assert!(complete("frob").1.is_empty());
+    }
+
+    #[test]
+    fn completes_options() {
+        assert_eq!(complete("set hid"), (4, vec!["hidden".into()]));
+        assert_eq!(complete("se nohid"), (3, vec!["nohidden".into()]));
+        assert_eq!(
+            complete("set hidden ig"),
+            (11, vec!["ignorecase".into(), "ignore".into()])
+        );
+        assert!(!complete("set ").1.iter().any(|name| name.starts_with("no")));
+    }
+
+    #[test]
+    fn completes_paths() {
+        let dir = env::temp_dir().join(format!("lens-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("file.txt"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let dir = format!("{}/", dir.display());
+
+        let line = format!("cexport {}", dir);
+        let (start, paths) = complete(&line);
+        assert_eq!(start, "cexport ".len());
+        assert_eq!(paths, [format!("{}file.txt", dir), format!("{}sub/", dir)]);
+        assert_eq!(
+            complete(&format!("{}.", line)).1,
+            [format!("{}.hidden", dir)]
+        );
+        assert!(complete(&format!("{}x", line)).1.is_empty());
+        // Commands without arguments have nothing to complete.
+        assert!(complete(&format!("copen {}", dir)).1.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+use std::ops::Range;
+
+const SCORE_MATCH: i64 = 16;
+const SCORE_GAP_START: i64 = -3;
+const SCORE_GAP_EXTENSION: i64 = -1;
+
+const BONUS_BOUNDARY: i64 = SCORE_MATCH / 2;
+const BONUS_NON_WORD: i64 = SCORE_MATCH / 2;
+const BONUS_CAMEL: i64 = BONUS_BOUNDARY + SCORE_GAP_EXTENSION;
+const BONUS_CONSECUTIVE: i64 = -(SCORE_GAP_START + SCORE_GAP_EXTENSION);
+const BONUS_SEPARATOR: i64 = BONUS_BOUNDARY + 1;
+const BONUS_WHITESPACE: i64 = BONUS_BOUNDARY + 2;
+const BONUS_FIRST_CHAR_MULTIPLIER: i64 = 2;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Whitespace,
+    Separator,
+    Delimiter,
+    NonWord,
+    Lower,
+    Upper,
+    Number,
+}
+
+impl Class {
+    fn of(c: char) -> Self {
+        match c {
+            c if c.is_whitespace() => Class::Whitespace,
+            '/' | '\\' => Class::Separator,
+            ',' | ':' | ';' | '|' => Class::Delimiter,
+            c if c.is_lowercase() => Class::Lower,
+            c if c.is_uppercase() => Class::Upper,
+            c if c.is_numeric() => Class::Number,
+            c if c.is_alphabetic() => Class::Lower,
+            _ => Class::NonWord,
+        }
+    }
+
+    fn is_word(self) -> bool {
+        matches!(self, Class::Lower | Class::Upper | Class::Number)
+    }
+}
+
+/// Bonus for matching a character of class `current` right after one of class `previous`.
+fn bonus(previous: Class, current: Class) -> i64 {
+    if !current.is_word() {
+        return match current {
+            Class::Whitespace => BONUS_WHITESPACE,
+            Class::Separator => BONUS_SEPARATOR,
+            _ => BONUS_NON_WORD,
+        };
+    }
+
+    match (previous, current) {
+        (Class::Whitespace, _) => BONUS_WHITESPACE,
+        (Class::Separator, _) => BONUS_SEPARATOR,
+        (Class::Delimiter | Class::NonWord, _) => BONUS_BOUNDARY,
+        (Class::Lower, Class::Upper) => BONUS_CAMEL,
+        (Class::Lower | Class::Upper, Class::Number) => BONUS_CAMEL,
+        _ => 0,
+    }
+}
+
+fn fold(c: char, case_sensitive: bool) -> char {
+    if case_sensitive {
+        return c;
+    }
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Scores lines against one pattern, keeping its tables between lines so a batch of
+/// results doesn't allocate for every one of them.
+#[derive(Debug, Default)]
+pub struct Scorer {
+    pattern: Vec<char>,
+    case_sensitive: bool,
+    chars: Vec<(usize, char)>,
+    folded: Vec<char>,
+    bonuses: Vec<i64>,
+    /// `score[i * m + j]`: best score with `pattern[i]` matched at `text[j]`.
+    score: Vec<Option<i64>>,
+    /// Bonus of the first character of the consecutive run ending at `i * m + j`.
+    chunk: Vec<i64>,
+    /// Where `pattern[i - 1]` sat for the best score at `i * m + j`.
+    from: Vec<usize>,
+}
+
+impl Scorer {
+    /// Matching is smart-case: case-sensitive once `pattern` has an uppercase letter.
+    pub fn new(pattern: &str) -> Self {
+        let case_sensitive = pattern.chars().any(char::is_uppercase);
+        Self {
+            pattern: pattern.chars().map(|c| fold(c, case_sensitive)).collect(),
+            case_sensitive,
+            ..Self::default()
+        }
+    }
+
+    /// Scores `text` fzf-style: every pattern character has to appear in order, matches
+    /// on word boundaries, path separators and camelCase humps earn bonuses, and gaps
+    /// between matched characters are penalised.
+    ///
+    /// Returns the score along with the byte ranges of the matched characters, or `None`
+    /// when the pattern isn't a subsequence of `text`.
+    pub fn matches(&mut self, text: &str) -> Option<(i64, Vec<Range<usize>>)> {
+        let pattern = &self.pattern;
+        if pattern.is_empty() {
+            return Some((0, Vec::new()));
+        }
+
+        let case_sensitive = self.case_sensitive;
+        self.chars.clear();
+        self.chars.extend(text.char_indices());
+        self.folded.clear();
+        self.folded
+            .extend(self.chars.iter().map(|&(_, c)| fold(c, case_sensitive)));
+        let (chars, folded) = (&self.chars, &self.folded);
+
+        // Cheap subsequence check before paying for the full table.
+        let mut remaining = pattern.iter().peekable();
+        folded.iter().for_each(|c| {
+            if remaining.peek() == Some(&c) {
+                remaining.next();
+            }
+        });
+        if remaining.peek().is_some() {
+            return None;
+        }
+
+        self.bonuses.clear();
+        self.bonuses
+            .extend(chars.iter().scan(Class::Whitespace, |previous, &(_, c)| {
+                let current = Class::of(c);
+                let bonus = bonus(*previous, current);
+                *previous = current;
+                Some(bonus)
+            }));
+        let bonuses = &self.bonuses;
+
+        let (n, m) = (pattern.len(), chars.len());
+        let at = |i: usize, j: usize| i * m + j;
+        self.score.clear();
+        self.score.resize(n * m, None);
+        self.chunk.clear();
+        self.chunk.resize(n * m, 0);
+        self.from.clear();
+        self.from.resize(n * m, 0);
+        let (score, chunk, from) = (&mut self.score, &mut self.chunk, &mut self.from);
+
+        for i in 0..n {
+            let mut gap: Option<(i64, usize)> = None;
+            for j in i..m {
+                if i > 0 && j >= 2 {
+                    let opened = score[at(i - 1, j - 2)].map(|s| (s + SCORE_GAP_START, j - 2));
+                    let extended = gap.map(|(s, k)| (s + SCORE_GAP_EXTENSION, k));
+                    gap = match (opened, extended) {
+                        (Some(a), Some(b)) => Some(if a.0 >= b.0 { a } else { b }),
+                        (a, b) => a.or(b),
+                    };
+                }
+                if folded[j] != pattern[i] {
+                    continue;
+                }
+
+                if i == 0 {
+                    score[at(i, j)] = Some(SCORE_MATCH + bonuses[j] * BONUS_FIRST_CHAR_MULTIPLIER);
+                    chunk[at(i, j)] = bonuses[j];
+                    continue;
+                }
+
+                let consecutive = (j > 0).then(|| score[at(i - 1, j - 1)]).flatten().map(|s| {
+                    let bonus = bonuses[j]
+                        .max(BONUS_CONSECUTIVE)
+                        .max(chunk[at(i - 1, j - 1)]);
+                    (s + SCORE_MATCH + bonus, j - 1)
+                });
+                let gapped = gap.map(|(s, k)| (s + SCORE_MATCH + bonuses[j], k));
+
+                let best = match (consecutive, gapped) {
+                    (Some(a), Some(b)) => Some(if a.0 >= b.0 { a } else { b }),
+                    (a, b) => a.or(b),
+                };
+                if let Some((s, k)) = best {
+                    score[at(i, j)] = Some(s);
+                    from[at(i, j)] = k;
+                    chunk[at(i, j)] = if k + 1 == j {
+                        chunk[at(i - 1, k)]
+                    } else {
+                        bonuses[j]
+                    };
+                }
+            }
+        }
+
+        let (mut j, best) = (0..m)
+            .filter_map(|j| score[at(n - 1, j)].map(|s| (j, s)))
+            .max_by_key(|&(j, s)| (s, std::cmp::Reverse(j)))?;
+
+        let mut positions = vec![j];
+        for i in (1..n).rev() {
+            j = from[at(i, j)];
+            positions.push(j);
+        }
+        positions.reverse();
+
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        positions.into_iter().for_each(|j| {
+            let (start, c) = chars[j];
+            let end = start + c.len_utf8();
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        });
+
+        Some((best, ranges))
+    }
+}
+
+/// Scores a single `text` against `pattern`, see [`Scorer::matches`].
+pub fn matches(pattern: &str, text: &str) -> Option<(i64, Vec<Range<usize>>)> {
+    Scorer::new(pattern).matches(text)
+}
+
+/// Builds a ripgrep regex that prefilters lines containing `pattern` as a subsequence.
+pub fn subsequence_regex(pattern: &str) -> String {
+    pattern
+        .chars()
+        .map(|c| regex::escape(c.encode_utf8(&mut [0; 4])))
+        .collect::<Vec<_>>()
+        .join(".*")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(pattern: &str, text: &str) -> i64 {
+        matches(pattern, text).expect("matches").0
+    }
+
+    /// The matched byte ranges as `(start, end)` pairs.
+    fn ranges(pattern: &str, text: &str) -> Vec<(usize, usize)> {
+        let ranges = matches(pattern, text).expect("matches").1;
+        ranges.into_iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn requires_a_subsequence() {
+        assert!(matches("abc", "axbxc").is_some());
+        assert!(matches("abc", "acb").is_none());
+        assert_eq!(matches("", "anything"), Some((0, Vec::new())));
+    }
+
+    #[test]
+    fn is_smart_case() {
+        assert!(matches("foo", "FOO").is_some());
+        assert!(matches("Foo", "foo").is_none());
+        assert!(matches("Foo", "Foo").is_some());
+    }
+
+    #[test]
+    fn returns_merged_byte_ranges() {
+        assert_eq!(ranges("foo", "a foo"), [(2, 5)]);
+        assert_eq!(ranges("fb", "foo_bar"), [(0, 1), (4, 5)]);
+        assert_eq!(ranges("é", "aé"), [(1, 3)]);
+    }
+
+    #[test]
+    fn prefers_a_prefix() {
+        assert!(score("foo", "foobar") > score("foo", "xxfoo"));
+    }
+
+    #[test]
+    fn prefers_word_boundaries() {
+        assert!(score("bar", "foo_bar") > score("bar", "foobar"));
+        assert!(score("bar", "src/bar") > score("bar", "foo_bar"));
+        assert!(score("fb", "FooBar") > score("fb", "foobar"));
+    }
+
+    #[test]
+    fn prefers_consecutive_matches() {
+        assert!(score("abc", "xabcx") > score("abc", "xaxbxcx"));
+        assert!(score("abc", "xaxbc") > score("abc", "xaxbxc"));
+    }
+
+    #[test]
+    fn picks_the_best_occurrence() {
+        // The later `bar` sits on a boundary and wins over the earlier one.
+        assert_eq!(ranges("bar", "foobar_bar"), [(7, 10)]);
+    }
+
+    #[test]
+    fn scorer_reuse_matches_fresh_scoring() {
+        let lines = ["a much longer line with foo in it", "f_o_o", "nope", "foo"];
+        let mut scorer = Scorer::new("foo");
+        for line in lines {
+            assert_eq!(scorer.matches(line), matches("foo", line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn escapes_the_prefilter_regex() {
+        assert_eq!(subsequence_regex("ab"), "a.*b");
+        assert_eq!(subsequence_regex("a.(b"), r"a.*\..*\(.*b");
+    }
+}
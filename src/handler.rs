@@ -1,148 +1,961 @@
 mod helpers;
 
 use crate::{
-    app::{App, AppResult, Mode, Window},
+    app::{App, AppResult, Match, Matcher, Message, Mode, Source, Window},
+    ex::{self, Completion},
+    keymap::{Action, Context, Key, Lookup},
+    options::{self, Value},
+    patch,
     tui::Tui,
+    vi::{self, Motion, Operator, Registers},
+};
+use crossterm::event::KeyEvent;
+pub use helpers::get_preview;
+use helpers::{
+    get_results, handle_exit_commands, mark_visual, open_editor, record_changes, record_failure,
+    select, set, sync_edit, undo_files,
 };
-use crossterm::event::{KeyCode, KeyEvent};
-use helpers::{get_preview, get_results, handle_exit_commands, handle_vi_command, open_editor};
 use ratatui::backend::CrosstermBackend;
-use std::io;
+use std::{cmp::Reverse, io, path::PathBuf};
+
+/// Reruns the search for the current query and refreshes the preview.
+pub fn refresh(app: &mut App) -> AppResult<()> {
+    get_results(app)?;
+    get_preview(app)?;
+
+    Ok(())
+}
+
+pub fn handle_search_events(
+    id: u64,
+    results: Vec<Match>,
+    skipped: usize,
+    done: bool,
+    app: &mut App,
+) -> AppResult<()> {
+    if !app.worker.is_current(id) {
+        return Ok(());
+    }
+
+    let previewed = app
+        .search
+        .result
+        .get(app.search.scroll)
+        .map(|r| r.path.clone());
+    let refresh_preview = match &previewed {
+        Some(path) => results.iter().any(|result| &result.path == path),
+        None => true,
+    };
+
+    app.search.searching = !done;
+    app.search.skipped += skipped;
+    app.search.result.extend(results);
+    if app.search.is_fuzzy() {
+        // Ranking moves rows around, so the highlighted one and the `V` anchor follow
+        // the result they were on.
+        let search = &mut app.search;
+        let id = |result: &Match| (result.path.clone(), result.line, result.column);
+        let scrolled = search.result.get(search.scroll).map(id);
+        let anchored = search.result.get(search.anchor).map(id);
+        search.result.sort_by_key(|result| Reverse(result.score));
+        let position = |target: Option<(PathBuf, usize, usize)>| {
+            target.and_then(|target| search.result.iter().position(|r| id(r) == target))
+        };
+        let (scroll, anchor) = (position(scrolled), position(anchored));
+        search.scroll = scroll.unwrap_or(search.scroll);
+        search.anchor = anchor.unwrap_or(search.anchor);
+    }
+    if refresh_preview {
+        get_preview(app)?;
+    }
+
+    Ok(())
+}
 
 pub fn handle_key_events(
     key_event: KeyEvent,
     app: &mut App,
     tui: &mut Tui<CrosstermBackend<io::Stderr>>,
 ) -> AppResult<()> {
-    match (key_event.code, &app.search.mode, &app.window) {
-        // Search
-        (KeyCode::Char(c), Mode::Insert, Window::Search) => {
+    app.message = None;
+    let key = Key::from(key_event);
+    if app.window == Window::Search {
+        app.history.record(&app.search, key);
+    }
+    app.pending.push(key);
+    dispatch(app, tui, false)?;
+    sync_edit(app);
+    commit_change(app);
+    get_preview(app)?;
+
+    Ok(())
+}
+
+/// Resolves ambiguous keys once they time out by running the mapping they already
+/// complete. A count, operator or register typed before them is kept for what follows.
+pub fn handle_tick(app: &mut App, tui: &mut Tui<CrosstermBackend<io::Stderr>>) -> AppResult<()> {
+    if !app.tick() {
+        return Ok(());
+    }
+    // A count, operator or register that nothing followed is dropped.
+    if app.pending.keys.is_empty() {
+        app.pending.clear();
+        return Ok(());
+    }
+
+    dispatch(app, tui, true)?;
+    // Whatever the keys left pending, like the operator of a `d` that could have been
+    // the start of a mapping, gets a timeout of its own.
+    app.pending.restart();
+    sync_edit(app);
+    commit_change(app);
+    get_preview(app)?;
+
+    Ok(())
+}
+
+/// Runs whatever the pending keys amount to. With `flush`, keys that could still grow
+/// into a longer mapping are resolved instead of waiting for more.
+fn dispatch(
+    app: &mut App,
+    tui: &mut Tui<CrosstermBackend<io::Stderr>>,
+    flush: bool,
+) -> AppResult<()> {
+    while !app.pending.keys.is_empty() {
+        if let Some(action) = app.pending.argument.take() {
+            let key = app.pending.keys.remove(0);
+            match key.char() {
+                Some(c) => resolve_argument(action, c, app)?,
+                None => app.pending.clear(),
+            }
+            continue;
+        }
+
+        let context = app.context();
+        let keymap = &app.config.keymap;
+
+        // Digits start a count unless mapped; `0` only continues one, otherwise it's a
+        // motion.
+        if let ([key], Context::Normal | Context::Visual | Context::Operator) =
+            (app.pending.keys.as_slice(), context)
+        {
+            let digit = key.char().and_then(|c| c.to_digit(10));
+            let counting = app.pending.count.is_some();
+            if let Some(digit) = digit.filter(|&digit| {
+                counting || (digit != 0 && keymap.lookup(context, &[*key]) == Lookup::None)
+            }) {
+                let count = app.pending.count.unwrap_or(0);
+                app.pending.count = Some(count.saturating_mul(10).saturating_add(digit as usize));
+                app.pending.keys.clear();
+                continue;
+            }
+        }
+
+        match keymap.lookup(context, &app.pending.keys) {
+            Lookup::Pending if !flush => break,
+            Lookup::Action(action) => {
+                app.pending.keys.clear();
+                resolve(action, app, tui)?;
+            }
+            // Nothing can extend what was typed: run the longest mapping it starts with,
+            // or type its first key, and retry with the rest like vim does.
+            _ => match keymap.longest_prefix(context, &app.pending.keys) {
+                Some((len, action)) => {
+                    app.pending.keys.drain(..len);
+                    resolve(action, app, tui)?;
+                }
+                None => {
+                    let key = app.pending.keys.remove(0);
+                    app.pending.count = None;
+                    app.pending.operator = None;
+                    type_key(key, app)?;
+                }
+            },
+        }
+    }
+
+    if app.pending.is_idle() {
+        app.pending.typed.clear();
+    }
+
+    Ok(())
+}
+
+/// Closes the undo step once a command is complete, keeping a whole insert, or a visual
+/// selection and what was done to it, as one.
+fn commit_change(app: &mut App) {
+    let open = matches!(app.search.mode, Mode::Insert | Mode::Replace | Mode::Visual);
+    if app.pending.is_idle() && !open && app.history.commit(&app.search) {
+        record_query(app);
+    }
+}
+
+/// Adds the current query to the search history, unless it's an edited result.
+fn record_query(app: &mut App) {
+    if app.edit.is_some() {
+        return;
+    }
+    let query = app.search.query.iter().collect::<String>();
+    if let Err(e) = app.search_history.record(&query) {
+        app.message = Some(Message::Error(format!("history: {}", e)));
+    }
+}
+
+/// Completes the word before the command-line cursor, or moves on to the next or
+/// previous completion while the last one is still there untouched.
+fn complete(app: &mut App, forward: bool) {
+    let command = &mut app.command;
+    let cycling = command.completion.as_ref().filter(|completion| {
+        let typed = command.query.get(completion.start..command.cursor);
+        typed.is_some_and(|typed| {
+            typed
+                .iter()
+                .copied()
+                .eq(completion.candidates[completion.index].chars())
+        })
+    });
+
+    let completion = match cycling {
+        Some(_) => {
+            let mut completion = command.completion.take().unwrap_or_default();
+            let len = completion.candidates.len();
+            completion.index = if forward {
+                (completion.index + 1) % len
+            } else {
+                (completion.index + len - 1) % len
+            };
+            completion
+        }
+        None => {
+            let line = command.query[..command.cursor].iter().collect::<String>();
+            let (start, candidates) = ex::complete(&line);
+            if candidates.is_empty() {
+                command.completion = None;
+                return;
+            }
+            let index = if forward { 0 } else { candidates.len() - 1 };
+            Completion {
+                start,
+                candidates,
+                index,
+            }
+        }
+    };
+
+    let candidate = completion.candidates[completion.index].chars();
+    let len = command
+        .query
+        .splice(completion.start..command.cursor, candidate)
+        .count();
+    command.cursor = command.cursor - len + completion.candidates[completion.index].chars().count();
+    command.completion = Some(completion);
+}
+
+/// Flips the toggle under the cursor of the options panel, or puts `:set name=value` on
+/// the command line to edit anything else.
+fn change_option(app: &mut App) -> AppResult<()> {
+    let Some(name) = options::names().nth(app.option) else {
+        return Ok(());
+    };
+    if let Some(Value::Bool(_)) = app.config.get(name) {
+        set(app, &[format!("inv{}", name)])?;
+        return Ok(());
+    }
+
+    let current = options::show(&app.config, name).unwrap_or_default();
+    app.command.return_to = Window::Options;
+    app.window = Window::Command;
+    app.command.query = format!(":set {}", current).chars().collect();
+    app.command.cursor = app.command.query.len();
+
+    Ok(())
+}
+
+/// Replaces the query with one from the history and searches for it.
+fn set_query(app: &mut App, query: String) -> AppResult<()> {
+    app.search.query = query.chars().collect();
+    app.search.cursor = app.search.query.len();
+    app.search.scroll = 0;
+    get_results(app)?;
+
+    Ok(())
+}
+
+/// Combines `action` with the count and operator typed before it.
+fn resolve(
+    action: Action,
+    app: &mut App,
+    tui: &mut Tui<CrosstermBackend<io::Stderr>>,
+) -> AppResult<()> {
+    // The options panel, the substitution review and the journal only move, change the
+    // row under the cursor, or close.
+    if matches!(
+        app.window,
+        Window::Options | Window::Review | Window::Journal
+    ) {
+        app.pending.count = None;
+        return match (&app.window, action) {
+            (
+                _,
+                Action::PreviousResult
+                | Action::NextResult
+                | Action::FirstResult
+                | Action::LastResult
+                | Action::Accept
+                | Action::ToggleMark
+                | Action::NormalMode
+                | Action::CommandLine,
+            )
+            | (Window::Options, Action::Options)
+            | (Window::Review, Action::MarkAll | Action::InvertMarks) => {
+                run_action(action, app, tui)
+            }
+            _ => Ok(()),
+        };
+    }
+
+    if action.takes_char() {
+        app.pending.argument = Some(action);
+        return Ok(());
+    }
+
+    let motion = match action {
+        Action::RepeatFind => app.search.find.map(Motion::RepeatFind),
+        Action::RepeatFindReverse => app
+            .search
+            .find
+            .map(|find| Motion::RepeatFind(find.reversed())),
+        action => action.motion(),
+    };
+    if let Some(motion) = motion {
+        return run_motion(motion, app);
+    }
+
+    // Operators and their shorthands act on the selection straight away in visual mode.
+    let operator = action
+        .operator()
+        .or(action.shorthand().map(|(operator, _)| operator));
+    if let (Mode::Visual, Some(operator)) = (&app.search.mode, operator) {
+        app.pending.count = None;
+        app.search.mode = Mode::Normal;
+        let range = app.search.selection();
+        let text = vi::apply(&mut app.search, operator, range);
+        return operated(operator, Some(text), app);
+    }
+
+    let count = app.pending.count.take();
+    match (app.pending.operator.take(), action.operator()) {
+        // `dd`, `cc`, `yy`
+        (Some((operator, _)), Some(repeated)) if operator == repeated => {
+            let text = vi::operate_line(&mut app.search, operator);
+            operated(operator, Some(text), app)?;
+        }
+        // Anything else cancels the operator, as in vim.
+        (Some(_), _) => {}
+        (None, Some(operator)) => {
+            app.pending.operator = Some((operator, count.unwrap_or(1)));
+        }
+        (None, None) => match (action.shorthand(), count) {
+            (Some((operator, motion)), count) => {
+                let text = match motion {
+                    Some(motion) => {
+                        vi::operate(&mut app.search, operator, motion, count.unwrap_or(1))
+                    }
+                    None => Some(vi::operate_line(&mut app.search, operator)),
+                };
+                operated(operator, text, app)?;
+            }
+            (None, count) if matches!(action, Action::PutAfter | Action::PutBefore) => {
+                let register = app.pending.register.take();
+                if let Some(text) = app.registers.get(register).map(str::to_string) {
+                    let after = action == Action::PutAfter;
+                    vi::put(&mut app.search, &text, after, count.unwrap_or(1));
+                    get_results(app)?;
+                }
+            }
+            // `5G` and `5gg` jump to the fifth result.
+            (None, Some(count)) if matches!(action, Action::FirstResult | Action::LastResult) => {
+                app.search.scroll = (count - 1).min(app.search.result.len().saturating_sub(1));
+            }
+            (None, Some(count))
+                if matches!(
+                    action,
+                    Action::PreviousResult
+                        | Action::NextResult
+                        | Action::ToggleMark
+                        | Action::Undo
+                        | Action::Redo
+                        | Action::Repeat
+                ) =>
+            {
+                for _ in 0..count {
+                    run_action(action, app, tui)?;
+                }
+            }
+            (None, _) => run_action(action, app, tui)?,
+        },
+    }
+    if app.pending.operator.is_none() {
+        app.pending.register = None;
+    }
+
+    Ok(())
+}
+
+/// Finishes a command that took a character, like `fx` or the `i(` in `di(`.
+fn resolve_argument(action: Action, c: char, app: &mut App) -> AppResult<()> {
+    if action == Action::Register {
+        if Registers::is_valid(c) {
+            app.pending.register = Some(c);
+        } else {
+            app.pending.clear();
+        }
+        return Ok(());
+    }
+    if let Some(find) = action.find(c) {
+        app.search.find = Some(find);
+        return run_motion(Motion::Find(find), app);
+    }
+    if action == Action::ReplaceChar {
+        return replace_char(c, app);
+    }
+
+    let object = vi::TextObject::new(c, action == Action::Around);
+    let count = app.pending.count.take().unwrap_or(1);
+    if app.search.mode == Mode::Visual {
+        let range =
+            object.and_then(|object| object.range(&app.search.query, app.search.cursor, count));
+        if let Some(range) = range.filter(|range| !range.is_empty()) {
+            app.search.query_anchor = range.start;
+            app.search.cursor = range.end - 1;
+        }
+        return Ok(());
+    }
+    match (app.pending.operator.take(), object) {
+        (Some((operator, times)), Some(object)) => {
+            let text = vi::operate_object(&mut app.search, operator, object, times * count);
+            operated(operator, text, app)?;
+        }
+        _ => app.pending.clear(),
+    }
+
+    Ok(())
+}
+
+/// `r`, overwriting the characters under the cursor or the whole visual selection.
+fn replace_char(c: char, app: &mut App) -> AppResult<()> {
+    let count = app.pending.count.take().unwrap_or(1);
+    app.pending.register = None;
+    let replaced = match app.search.mode {
+        Mode::Visual => {
+            let range = app.search.selection();
+            app.search.query[range.clone()].fill(c);
+            app.search.cursor = range.start;
+            app.search.mode = Mode::Normal;
+            !range.is_empty()
+        }
+        _ => vi::replace(&mut app.search, c, count),
+    };
+    if replaced {
+        get_results(app)?;
+    }
+
+    Ok(())
+}
+
+/// Moves the cursor, or applies the pending operator over the motion.
+fn run_motion(motion: Motion, app: &mut App) -> AppResult<()> {
+    // The command line only moves, and never onto its `:`.
+    if app.window == Window::Command {
+        let command = &mut app.command;
+        command.cursor = match motion {
+            Motion::Left => command.cursor.saturating_sub(1).max(1),
+            Motion::Right => (command.cursor + 1).min(command.query.len()),
+            Motion::LineStart => 1,
+            Motion::LineEnd => command.query.len(),
+            _ => command.cursor,
+        };
+        return Ok(());
+    }
+
+    let count = app.pending.count.take().unwrap_or(1);
+    match app.pending.operator.take() {
+        Some((operator, times)) => {
+            let text = vi::operate(&mut app.search, operator, motion, times * count);
+            operated(operator, text, app)?;
+        }
+        None => {
+            app.pending.register = None;
+            vi::move_cursor(&mut app.search, motion, count);
+        }
+    }
+
+    Ok(())
+}
+
+/// Saves what an operator covered to the chosen register and reruns the search if the
+/// query changed.
+fn operated(operator: Operator, text: Option<String>, app: &mut App) -> AppResult<()> {
+    let register = app.pending.register.take();
+    let Some(text) = text else {
+        return Ok(());
+    };
+
+    app.registers.store(register, text, operator);
+    if operator != Operator::Yank {
+        get_results(app)?;
+    }
+
+    Ok(())
+}
+
+/// Inserts an unmapped character into the query or the command line.
+fn type_key(key: Key, app: &mut App) -> AppResult<()> {
+    let Some(c) = key.char() else {
+        return Ok(());
+    };
+
+    match (&app.window, &app.search.mode) {
+        (Window::Command, _) => {
+            app.command.query.insert(app.command.cursor, c);
+            app.command.cursor += 1;
+        }
+        (Window::History, _) => {
+            app.search_history.filter.push(c);
+            app.search_history.selected = 0;
+        }
+        (Window::Search, Mode::Insert) => {
             if app.search.cursor > app.search.query.len() {
                 app.search.cursor = app.search.query.len();
             }
             app.search.query.insert(app.search.cursor, c);
             app.search.cursor += 1;
-            app.search.scroll = 0;
             get_results(app)?;
         }
-        (KeyCode::Backspace, Mode::Insert, Window::Search) => {
-            if app.search.cursor > 0 {
-                app.search.query.remove(app.search.cursor - 1);
-                app.search.cursor -= 1;
+        (Window::Search, Mode::Replace) => {
+            let cursor = app.search.cursor.min(app.search.query.len());
+            match app.search.query.get_mut(cursor) {
+                Some(old) => app.search.replaced.push(Some(std::mem::replace(old, c))),
+                None => {
+                    app.search.query.push(c);
+                    app.search.replaced.push(None);
+                }
             }
-
+            app.search.cursor = cursor + 1;
             get_results(app)?;
         }
-        (KeyCode::Esc, Mode::Insert, Window::Search) => {
-            app.search.mode = Mode::Normal;
-            if app.search.cursor > 0 {
-                app.search.cursor -= 1
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn run_action(
+    action: Action,
+    app: &mut App,
+    tui: &mut Tui<CrosstermBackend<io::Stderr>>,
+) -> AppResult<()> {
+    match action {
+        // Options
+        Action::Options | Action::NormalMode if app.window == Window::Options => {
+            app.window = Window::Search;
+        }
+        Action::PreviousResult if app.window == Window::Options => {
+            let last = options::names().count() - 1;
+            app.option = app.option.checked_sub(1).unwrap_or(last);
+        }
+        Action::NextResult if app.window == Window::Options => {
+            app.option = (app.option + 1) % options::names().count();
+        }
+        Action::FirstResult if app.window == Window::Options => app.option = 0,
+        Action::LastResult if app.window == Window::Options => {
+            app.option = options::names().count() - 1;
+        }
+        Action::Accept | Action::ToggleMark if app.window == Window::Options => {
+            change_option(app)?;
+        }
+        Action::Options => app.window = Window::Options,
+
+        // Review
+        Action::NormalMode if app.window == Window::Review => {
+            app.review = None;
+            app.window = Window::Search;
+        }
+        Action::PreviousResult
+        | Action::NextResult
+        | Action::FirstResult
+        | Action::LastResult
+        | Action::ToggleMark
+        | Action::MarkAll
+        | Action::InvertMarks
+            if app.window == Window::Review =>
+        {
+            if let Some(review) = &mut app.review {
+                match action {
+                    Action::PreviousResult => review.previous(),
+                    Action::NextResult => review.next(),
+                    Action::FirstResult => review.first(),
+                    Action::LastResult => review.last(),
+                    Action::ToggleMark => review.toggle(),
+                    Action::MarkAll => review.accept_all(),
+                    _ => review.invert(),
+                }
+            }
+        }
+        Action::Accept if app.window == Window::Review => {
+            let Some(review) = app.review.take() else {
+                return Ok(());
+            };
+            match patch::write(&review.patches) {
+                Ok(written) => {
+                    app.window = Window::Search;
+                    let message = format!(
+                        "substituted {}",
+                        patch::lines_in_files(written.lines, written.files)
+                    );
+                    app.message = Some(record_changes(
+                        app,
+                        &review.command,
+                        &review.patches,
+                        message,
+                    ));
+                    get_results(app)?;
+                }
+                // Nothing was written, so the review stays open.
+                Err(failed) if failed.written.files == 0 => {
+                    app.review = Some(review);
+                    app.message = Some(Message::Error(failed.error));
+                }
+                Err(failed) => {
+                    app.window = Window::Search;
+                    app.message = Some(record_failure(
+                        app,
+                        &review.command,
+                        &review.patches,
+                        failed,
+                    ));
+                    get_results(app)?;
+                }
+            }
+        }
+
+        // Journal
+        Action::NormalMode if app.window == Window::Journal => {
+            app.window = Window::Search;
+        }
+        Action::PreviousResult | Action::NextResult | Action::FirstResult | Action::LastResult
+            if app.window == Window::Journal =>
+        {
+            let last = app.journal.batches().count().saturating_sub(1);
+            let selected = &mut app.journal.selected;
+            *selected = match action {
+                Action::PreviousResult => selected.checked_sub(1).unwrap_or(last),
+                Action::NextResult if *selected < last => *selected + 1,
+                Action::NextResult | Action::FirstResult => 0,
+                _ => last,
+            };
+        }
+        Action::Accept | Action::ToggleMark if app.window == Window::Journal => {
+            undo_files(app, Some(app.journal.selected))?;
+        }
+
+        // History
+        Action::NormalMode if app.window == Window::History => {
+            app.window = Window::Search;
+        }
+        Action::Backspace if app.window == Window::History => {
+            app.search_history.filter.pop();
+            app.search_history.selected = 0;
+        }
+        Action::OlderQuery if app.window == Window::History => {
+            app.search_history.selected = app.search_history.selected.saturating_sub(1);
+        }
+        Action::NewerQuery if app.window == Window::History => {
+            let last = app.search_history.matches().len().saturating_sub(1);
+            app.search_history.selected = (app.search_history.selected + 1).min(last);
+        }
+        Action::Accept if app.window == Window::History => {
+            app.window = Window::Search;
+            let matches = app.search_history.matches();
+            let Some((query, _)) = matches.get(app.search_history.selected) else {
+                return Ok(());
+            };
+            let query = query.to_string();
+            set_query(app, query)?;
+            record_query(app);
+        }
+        // The query line holds a result while editing, so there's no query to swap in.
+        Action::SearchHistory | Action::ToggleMatcher | Action::ToggleSource
+            if app.edit.is_some() =>
+        {
+            app.message = Some(Message::Error(
+                "not while editing the results, :w or :edit! first".to_string(),
+            ));
+        }
+        Action::SearchHistory => {
+            app.window = Window::History;
+            app.search_history.filter.clear();
+            app.search_history.selected = 0;
+        }
+
+        // Command
+        Action::NormalMode if app.window == Window::Command => {
+            app.command.query.clear();
+            app.command.cursor = 0;
+            app.window = app.command.return_to;
+        }
+        Action::Backspace if app.window == Window::Command => {
+            if app.command.query.len() <= 1 {
+                app.window = app.command.return_to;
+                app.command.query.clear();
+                app.command.cursor = 0;
+            } else if app.command.cursor > 1 {
+                app.command.query.remove(app.command.cursor - 1);
+                app.command.cursor -= 1;
             }
         }
-        (KeyCode::Char('G'), Mode::Normal, Window::Search) => {
-            app.search.scroll = app.search.result.len() - 1;
+        Action::Accept if app.window == Window::Command => {
+            app.window = app.command.return_to;
+            let line = app.command.query.iter().collect::<String>();
+            if let Err(e) = app.command_history.record(&line) {
+                app.message = Some(Message::Error(format!("history: {}", e)));
+            }
+            handle_exit_commands(app, tui)?;
+            app.command.query.clear();
+            app.command.cursor = 0;
         }
-        (KeyCode::Enter, _, Window::Search) => {
-            if app.search.result.len() > 0 {
-                open_editor(app, tui)?;
+        Action::OlderQuery | Action::NewerQuery if app.window == Window::Command => {
+            let current = app.command.query.iter().collect::<String>();
+            let recalled = match action {
+                Action::OlderQuery => app.command_history.older(&current),
+                _ => app.command_history.newer(&current),
+            };
+            if let Some(line) = recalled {
+                app.command.query = line.chars().collect();
+                app.command.cursor = app.command.query.len();
             }
         }
-        (KeyCode::Char('k') | KeyCode::Up, _, Window::Search) => {
+        Action::Complete | Action::CompletePrevious => {
+            complete(app, action == Action::Complete);
+        }
+        Action::CommandLine => {
+            app.command.return_to = app.window;
+            app.window = Window::Command;
+            app.command.query.clear();
+            app.command.query.push(':');
+            app.command.cursor = 1;
+        }
+
+        // Search
+        Action::ToggleMatcher => {
+            app.search.matcher = match app.search.matcher {
+                Matcher::Regex => Matcher::Fuzzy,
+                Matcher::Fuzzy => Matcher::Regex,
+            };
+            get_results(app)?;
+        }
+        Action::ToggleSource => {
+            app.search.source = match app.search.source {
+                Source::Content => Source::Files,
+                Source::Files => Source::Content,
+                Source::Stdin => Source::Stdin,
+            };
+            get_results(app)?;
+        }
+        Action::MarkAll => {
+            app.search
+                .result
+                .iter_mut()
+                .filter(|result| !result.context)
+                .for_each(|result| result.marked = true);
+        }
+        Action::Accept => {
+            record_query(app);
+            if app.search.mode == Mode::VisualLine {
+                mark_visual(app);
+            }
+            match app.search.source {
+                Source::Stdin => select(app),
+                _ if app.search.selected().is_empty() => {}
+                _ => {
+                    if let Err(e) = open_editor(app, tui) {
+                        app.message = Some(Message::Error(e.to_string()));
+                    }
+                }
+            }
+        }
+        Action::FirstResult => app.search.scroll = 0,
+        Action::LastResult => {
+            app.search.scroll = app.search.result.len().saturating_sub(1);
+        }
+        Action::PreviousResult if !app.search.result.is_empty() => {
             if app.search.scroll == 0 {
                 app.search.scroll = app.search.result.len();
             }
             app.search.scroll -= 1;
         }
-        (KeyCode::Char('j') | KeyCode::Down, _, Window::Search) => {
+        Action::NextResult if !app.search.result.is_empty() => {
             app.search.scroll += 1;
-            if app.search.scroll == app.search.result.len() {
+            if app.search.scroll >= app.search.result.len() {
                 app.search.scroll = 0;
             }
         }
-        (KeyCode::Char('D'), Mode::Normal, Window::Search) => {
-            if app.search.query.len() > 0 {
-                app.search
-                    .query
-                    .drain(app.search.cursor..app.search.query.len());
-                app.search.cursor -= 1;
+        Action::ToggleMark => {
+            if let Some(result) = app.search.result.get_mut(app.search.scroll) {
+                result.marked = !result.marked && !result.context;
+                app.search.scroll = (app.search.scroll + 1).min(app.search.result.len() - 1);
             }
         }
-        (KeyCode::Char('I'), Mode::Normal, Window::Search) => {
-            app.search.cursor = 0;
-            app.search.mode = Mode::Insert
+        Action::InvertMarks => {
+            app.search
+                .result
+                .iter_mut()
+                .filter(|result| !result.context)
+                .for_each(|result| result.marked = !result.marked);
         }
-        (KeyCode::Char('A'), Mode::Normal, Window::Search) => {
-            app.search.cursor = app.search.query.len();
-            app.search.mode = Mode::Insert;
+        Action::VisualLine => {
+            app.search.anchor = app.search.scroll;
+            app.search.mode = Mode::VisualLine;
         }
-        (KeyCode::Char('h'), Mode::Normal, Window::Search) => {
-            if app.search.cursor > 0 {
-                app.search.cursor -= 1;
+        Action::MarkVisual if app.search.mode == Mode::VisualLine => mark_visual(app),
+        Action::Visual => match app.search.mode {
+            Mode::Visual => app.search.mode = Mode::Normal,
+            _ => {
+                app.search.cursor = app
+                    .search
+                    .cursor
+                    .min(app.search.query.len().saturating_sub(1));
+                app.search.query_anchor = app.search.cursor;
+                app.search.mode = Mode::Visual;
             }
+        },
+        Action::SwapEnds => {
+            std::mem::swap(&mut app.search.cursor, &mut app.search.query_anchor);
         }
-        (KeyCode::Char('l'), Mode::Normal, Window::Search) => {
-            if app.search.cursor < app.search.query.len() - 1 {
-                app.search.cursor += 1;
+        Action::NormalMode => {
+            let typing = matches!(app.search.mode, Mode::Insert | Mode::Replace);
+            if typing && app.search.cursor > 0 {
+                app.search.cursor -= 1
             }
+            app.search.mode = Mode::Normal;
         }
-        (KeyCode::Char('i'), Mode::Normal, Window::Search) => {
-            app.search.mode = Mode::Insert;
-        }
-        (KeyCode::Char('a'), Mode::Normal, Window::Search) => {
-            app.search.mode = Mode::Insert;
-            if app.search.cursor < app.search.query.len() {
-                app.search.cursor += 1;
+
+        // Query
+        Action::OlderQuery | Action::NewerQuery
+            if app.window == Window::Search && app.edit.is_none() =>
+        {
+            let current = app.search.query.iter().collect::<String>();
+            let recalled = match action {
+                Action::OlderQuery => app.search_history.older(&current),
+                _ => app.search_history.newer(&current),
+            };
+            if let Some(query) = recalled {
+                set_query(app, query)?;
             }
         }
-        (KeyCode::Char('x'), Mode::Normal, Window::Search) => {
-            if app.search.cursor <= app.search.query.len() && app.search.query.len() > 0 {
-                app.search.query.remove(app.search.cursor);
+        Action::OlderQuery | Action::NewerQuery => {}
+        // Backspace only takes back what this replace overwrote; before that it just
+        // moves left.
+        Action::Backspace if app.search.mode == Mode::Replace => {
+            if app.search.cursor > 0 {
+                app.search.cursor -= 1;
+                match app.search.replaced.pop() {
+                    Some(Some(c)) => app.search.query[app.search.cursor] = c,
+                    Some(None) => {
+                        app.search.query.remove(app.search.cursor);
+                    }
+                    None => {}
+                }
+                get_results(app)?;
             }
-
-            if app.search.cursor >= app.search.query.len() {
+        }
+        Action::Backspace => {
+            if app.search.cursor > 0 {
+                app.search.query.remove(app.search.cursor - 1);
                 app.search.cursor -= 1;
             }
+
             get_results(app)?;
         }
-
-        // Command
-        (KeyCode::Esc, _, Window::Command) => {
-            app.command.query.clear();
-            app.window = Window::Search;
+        Action::InsertStart => {
+            app.search.cursor = 0;
+            app.search.mode = Mode::Insert
         }
-        (KeyCode::Char(c), _, Window::Command) => {
-            app.command.query.push(c);
-            app.command.cursor += 1;
+        Action::AppendEnd => {
+            app.search.cursor = app.search.query.len();
+            app.search.mode = Mode::Insert;
         }
-        (KeyCode::Backspace, _, Window::Command) => {
-            if app.command.query.len() <= 1 {
-                app.window = Window::Search;
-            }
-
-            app.command.query.pop();
-            app.command.cursor -= 1;
+        Action::Insert => {
+            app.search.mode = Mode::Insert;
         }
-        (KeyCode::Enter, _, Window::Command) => {
-            app.window = Window::Search;
-            handle_exit_commands(app)?;
+        Action::ReplaceMode => {
+            app.search.replaced.clear();
+            app.search.mode = Mode::Replace;
         }
-
-        // General
-        (KeyCode::Char(':'), Mode::Normal, _) => {
-            app.window = Window::Command;
-            app.command.query.clear();
-            app.command.query.push(':');
-            app.command.cursor += 1;
+        Action::Append => {
+            app.search.mode = Mode::Insert;
+            if app.search.cursor < app.search.query.len() {
+                app.search.cursor += 1;
+            }
         }
-        (KeyCode::Char(c), _, _) => {
-            app.vi_command.push_str(&c.to_string());
-            handle_vi_command(app)?;
+        Action::Undo | Action::Redo => {
+            app.history.forget();
+            let changed = match action {
+                Action::Undo => app.history.undo(&mut app.search),
+                _ => app.history.redo(&mut app.search),
+            };
+            if changed {
+                get_results(app)?;
+            } else {
+                let which = if action == Action::Undo {
+                    "oldest"
+                } else {
+                    "newest"
+                };
+                app.message = Some(Message::Info(format!("already at {} change", which)));
+            }
         }
-        (KeyCode::Esc, _, _) => app.vi_command = String::new(),
-        _ => {}
+        Action::Repeat => {
+            for key in app.history.repeat() {
+                app.pending.push(key);
+                dispatch(app, tui, false)?;
+            }
+        }
+        Action::PreviousResult | Action::NextResult | Action::MarkVisual => {}
+        // Motions, operators and their shorthands are resolved before getting here.
+        Action::CursorLeft
+        | Action::CursorRight
+        | Action::WordForward
+        | Action::WordBackward
+        | Action::WordEnd
+        | Action::BigWordForward
+        | Action::BigWordBackward
+        | Action::BigWordEnd
+        | Action::LineStart
+        | Action::FirstNonBlank
+        | Action::LineEnd
+        | Action::FindForward
+        | Action::FindBackward
+        | Action::TillForward
+        | Action::TillBackward
+        | Action::RepeatFind
+        | Action::RepeatFindReverse
+        | Action::Inner
+        | Action::Around
+        | Action::Delete
+        | Action::Change
+        | Action::Yank
+        | Action::DeleteChar
+        | Action::DeleteToEnd
+        | Action::YankLine
+        | Action::Substitute
+        | Action::SubstituteLine
+        | Action::ChangeToEnd
+        | Action::ReplaceChar
+        | Action::PutAfter
+        | Action::PutBefore
+        | Action::Register => {}
     }
 
-    get_preview(app)?;
-
     Ok(())
 }
@@ -1,152 +1,642 @@
 use ratatui::backend::CrosstermBackend;
 
-use crate::app::{App, Window};
+use crate::app::{App, Matcher, Message, Mode, Source, Window};
+use crate::config::Config;
+use crate::editor::Editor;
+use crate::fuzzy;
+use crate::nvim::Nvim;
 use crate::tui::Tui;
-use std::{env, process::Command};
+use crate::{
+    app::Match,
+    edit::Edit,
+    ex::{self, Ex},
+    options,
+    patch::{self, FilePatch},
+    substitute::{Review, Substitute},
+    vi::History,
+};
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process,
+    time::SystemTime,
+};
 use std::{
-    fs::File,
+    fs::{self, File},
     io::{self, BufRead},
-    path::Path,
 };
 
+/// Opens every selected location in the user's editor, one after another, or sends
+/// them to the running Neovim when a server is configured.
 pub fn open_editor(
     app: &mut App,
     tui: &mut Tui<CrosstermBackend<io::Stderr>>,
 ) -> anyhow::Result<()> {
-    let editor = match env::var("EDITOR") {
-        Ok(editor) => editor,
-        Err(_) => return Ok(()),
-    };
-    let result = app
-        .search
-        .result
-        .get(app.search.scroll)
-        .unwrap()
-        .split(":")
-        .collect::<Vec<&str>>();
-
-    let (line, column) = (result[1], result[2]);
-    let command = match editor.as_ref() {
-        "vim" | "nvim" => format!("+normal {}G{}|", line, column),
-        "emacs" => format!("+{}:{}", line, column),
-        _ => format!(""),
+    if let Some(address) = &app.server {
+        return Nvim::connect(address)
+            .and_then(|mut nvim| nvim.open(&app.search.selected()))
+            .map_err(|e| anyhow::anyhow!("neovim at {}: {}", address, e));
+    }
+
+    let Some(editor) = Editor::from_env() else {
+        anyhow::bail!("no editor configured, set $VISUAL or $EDITOR");
     };
 
     let _ = tui.pause();
-    Command::new(editor)
-        .arg(command)
-        .arg(result[0])
-        .spawn()?
-        .wait()?;
+    let status = app
+        .search
+        .selected()
+        .into_iter()
+        .try_for_each(|result| -> io::Result<()> {
+            editor
+                .open(
+                    &app.config.editors,
+                    &result.path,
+                    result.line,
+                    result.column,
+                )
+                .spawn()?
+                .wait()?;
+            Ok(())
+        });
     let _ = tui.resume();
-
-    Ok(())
+    status.map_err(|e| anyhow::anyhow!("failed to launch {}: {}", editor.name, e))
 }
 
 pub fn get_results(app: &mut App) -> anyhow::Result<()> {
-    if app.search.query.len() > 0 {
-        app.search.result = String::from_utf8_lossy(
-            &Command::new("rg")
-                .args(&app.args)
-                .arg("--color=never")
-                .arg("--no-heading")
-                .arg("--with-filename")
-                .arg("--line-number")
-                .arg("--column")
-                .arg("--smart-case")
-                .arg(app.search.query.iter().collect::<String>())
-                .output()?
-                .stdout,
-        )
-        .lines()
-        .map(|line| line.to_string())
-        .collect::<Vec<String>>();
+    // While results are being edited the query line holds one of them, not a query.
+    if app.edit.is_some() {
+        return Ok(());
+    }
+    if app.search.query.is_empty() && app.search.source == Source::Content {
+        app.worker.cancel();
+        app.search.searching = false;
+        app.search.skipped = 0;
+        app.search.result = Vec::new();
+        return Ok(());
+    }
 
+    let query = app.search.query.iter().collect::<String>();
+    if app.search.source == Source::Stdin {
+        app.worker.filter(app.input.clone(), query)?;
+        app.search.searching = true;
+        app.search.skipped = 0;
+        app.search.result.clear();
+        app.search.scroll = 0;
         return Ok(());
     }
 
-    app.search.result = Vec::new();
+    let mut args = app.args.iter().map(OsString::from).collect::<Vec<_>>();
+    let fuzzy = match (app.search.source, app.search.matcher) {
+        (Source::Files | Source::Stdin, matcher) => {
+            args.push("--files".into());
+            args.extend(
+                app.config
+                    .search
+                    .flags(Source::Files, matcher)
+                    .into_iter()
+                    .map(OsString::from),
+            );
+            Some(query)
+        }
+        (Source::Content, matcher) => {
+            args.extend(["--json", "--line-buffered"].map(OsString::from));
+            args.extend(
+                app.config
+                    .search
+                    .flags(Source::Content, matcher)
+                    .into_iter()
+                    .map(OsString::from),
+            );
+            args.push("--regexp".into());
+            match matcher {
+                Matcher::Regex => {
+                    args.push(query.into());
+                    None
+                }
+                Matcher::Fuzzy => {
+                    args.push(fuzzy::subsequence_regex(&query).into());
+                    Some(query)
+                }
+            }
+        }
+    };
+    args.extend(app.paths.iter().map(OsString::from));
+
+    app.worker.spawn(app.search.source, args, fuzzy)?;
+    app.search.searching = true;
+    app.search.skipped = 0;
+    app.search.searched_at = Some(SystemTime::now());
+    app.search.result.clear();
+    app.search.scroll = 0;
+
     Ok(())
 }
 
 pub fn get_preview(app: &mut App) -> anyhow::Result<()> {
-    if app.search.result.len() == 0 {
+    let result = match app.search.source {
+        Source::Stdin => None,
+        _ => app.search.result.get(app.search.scroll),
+    };
+    let Some(result) = result else {
         app.search.preview = String::new();
+        app.search.highlights = Vec::new();
         return Ok(());
-    }
-    let result = app
-        .search
-        .result
-        .get(app.search.scroll)
-        .unwrap()
-        .split(":")
-        .collect::<Vec<&str>>();
-    let file = File::open(Path::new(result[0]));
+    };
+    let file = File::open(&result.path);
+    let source = app.search.source;
 
     if let Ok(file) = file {
         let reader = io::BufReader::new(file);
 
-        let x = result[1].parse::<usize>()?;
+        // File names have no matching line, so the preview just shows the top of the
+        // file.
+        let x = match source {
+            Source::Content => result.line,
+            Source::Files | Source::Stdin => 0,
+        };
 
-        let start = if x > 25_usize { x - 25 } else { 0 };
-        let end = start + 50;
+        let context = app.config.preview_context;
+        let start = x.saturating_sub(context);
+        let end = start + context * 2;
         app.search.line = x - start;
 
         app.search.preview = reader
             .lines()
             .enumerate()
             .skip(start)
-            .take_while(|&(index, _)| index + 1 <= end)
+            .take_while(|&(index, _)| index < end)
             .map(|(_, line)| line.unwrap_or("".to_string()))
             .collect::<Vec<String>>()
             .join("\n");
+
+        // Every hit from the same file that falls inside the window gets highlighted.
+        app.search.highlights = vec![Vec::new(); end - start];
+        if source != Source::Content {
+            return Ok(());
+        }
+        app.search
+            .result
+            .iter()
+            .filter(|other| other.path == result.path && other.line > start && other.line <= end)
+            .for_each(|other| {
+                app.search.highlights[other.line - start - 1].extend(other.submatches.clone())
+            });
         return Ok(());
     }
 
     Ok(())
 }
 
-pub fn handle_vi_command(app: &mut App) -> anyhow::Result<()> {
-    match app.vi_command.as_ref() {
-        "gg" => {
-            if app.window == Window::Search {
-                app.search.scroll = 0;
-            } else {
-                app.search.scroll = 0;
+/// Accepts the selected lines in `--filter` mode and quits so they can be printed.
+pub fn select(app: &mut App) {
+    app.output = Some(
+        app.search
+            .selected()
+            .into_iter()
+            .map(|result| result.text.clone())
+            .collect(),
+    );
+    app.quit();
+}
+
+/// Toggles the mark on every row of the `V` selection and leaves visual mode.
+pub fn mark_visual(app: &mut App) {
+    let range = app.search.visual_range();
+    app.search
+        .result
+        .iter_mut()
+        .skip(*range.start())
+        .take(range.count())
+        .filter(|result| !result.context)
+        .for_each(|result| result.marked = !result.marked);
+    app.search.mode = Mode::Normal;
+}
+
+/// Writes the marked results, or all of them when nothing is marked, to `path` as a
+/// `file:line:col:text` errorfile and returns how many entries were written.
+fn write_quickfix(app: &App, path: &Path) -> anyhow::Result<usize> {
+    if app.search.source == Source::Stdin {
+        anyhow::bail!("filter results have no file locations to export");
+    }
+
+    let marked = app.search.result.iter().any(|result| result.marked);
+    let entries = app
+        .search
+        .result
+        .iter()
+        .filter(|result| (result.marked || !marked) && !result.context)
+        .map(|result| {
+            format!(
+                "{}:{}:{}:{}\n",
+                result.path.display(),
+                result.line,
+                result.column,
+                result.text
+            )
+        })
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        anyhow::bail!("no results to export");
+    }
+
+    fs::write(path, entries.concat())?;
+    Ok(entries.len())
+}
+
+fn quickfix_path() -> PathBuf {
+    env::temp_dir().join(format!("lens-{}.err", process::id()))
+}
+
+/// Exports the results as a quickfix list and opens it with `$EDITOR -q`.
+fn open_quickfix(app: &mut App, tui: &mut Tui<CrosstermBackend<io::Stderr>>) -> anyhow::Result<()> {
+    let Some(editor) = Editor::from_env() else {
+        anyhow::bail!("no editor configured, set $VISUAL or $EDITOR");
+    };
+    if !matches!(editor.name.as_ref(), "vim" | "nvim") {
+        anyhow::bail!(
+            "{} can't load a quickfix list, use :cexport instead",
+            editor.name
+        );
+    }
+
+    let path = quickfix_path();
+    write_quickfix(app, &path)?;
+
+    let _ = tui.pause();
+    let status = editor
+        .command()
+        .arg("-q")
+        .arg(&path)
+        .spawn()
+        .and_then(|mut child| child.wait());
+    let _ = tui.resume();
+    let _ = fs::remove_file(&path);
+    status.map_err(|e| anyhow::anyhow!("failed to launch {}: {}", editor.name, e))?;
+
+    Ok(())
+}
+
+/// `:set`, changing settings and rerunning the search, or showing them.
+pub fn set(app: &mut App, args: &[String]) -> anyhow::Result<()> {
+    if args.is_empty() {
+        app.message = Some(Message::Info(options::list(&app.config)));
+        return Ok(());
+    }
+
+    let mut shown = Vec::new();
+    let mut changed = false;
+    for arg in args {
+        match options::apply(&mut app.config, arg) {
+            Ok(Some(value)) => shown.push(value),
+            Ok(None) => changed = true,
+            Err(e) => {
+                app.message = Some(Message::Error(e));
+                break;
             }
         }
-        "dd" => {
-            app.search.query.clear();
-            get_results(app)?;
+    }
+    if !shown.is_empty() && app.message.is_none() {
+        app.message = Some(Message::Info(shown.join("  ")));
+    }
+    if changed {
+        get_results(app)?;
+    }
+
+    Ok(())
+}
+
+/// `:s`, working out the replacements on the marked results, or all of them with `%`,
+/// and opening them for review.
+fn substitute(app: &mut App, ex: &Ex) {
+    let error = if app.edit.is_some() {
+        "the results are being edited, :w or :edit! first"
+    } else if app.search.source != Source::Content {
+        "only content results can be substituted"
+    } else {
+        ""
+    };
+    if !error.is_empty() {
+        app.message = Some(Message::Error(error.to_string()));
+        return;
+    }
+
+    let query = app.search.query.iter().collect::<String>();
+    let options = &app.config.search;
+    let substitute = match Substitute::parse(&ex.raw, &query, options.ignorecase, options.smartcase)
+    {
+        Ok(substitute) => substitute,
+        Err(e) => {
+            app.message = Some(Message::Error(e));
+            return;
+        }
+    };
+    let results = if ex.all {
+        app.search.result.iter().collect::<Vec<&Match>>()
+    } else {
+        app.search.selected()
+    };
+
+    let patches = substitute.patches(&results);
+    if patches.is_empty() {
+        app.message = Some(Message::Error("pattern not found".to_string()));
+        return;
+    }
+    let range = if ex.all { "%" } else { "" };
+    app.review = Some(Review {
+        command: format!(":{}s{}", range, ex.raw),
+        patches,
+        current: 0,
+    });
+    app.window = Window::Review;
+}
+
+/// `:edit`, making the results editable: the highlighted one is loaded into the query
+/// line, and the query is put aside until `:w` or `:edit!`.
+fn start_editing(app: &mut App) {
+    let error = match (app.search.source, &app.edit) {
+        (_, Some(_)) => "already editing the results",
+        (Source::Content, None) if app.search.searching => "wait for the search to finish",
+        (Source::Content, None) if app.search.result.is_empty() => "no results to edit",
+        (Source::Content, None) => "",
+        _ => "only content results can be edited",
+    };
+    if !error.is_empty() {
+        app.message = Some(Message::Error(error.to_string()));
+        return;
+    }
+
+    app.edit = Some(Edit {
+        query: std::mem::take(&mut app.search.query),
+        cursor: app.search.cursor,
+        row: usize::MAX,
+        originals: Default::default(),
+        since: app.search.searched_at,
+    });
+    app.search.mode = Mode::Normal;
+    sync_edit(app);
+}
+
+/// Keeps the edited result and the query line in step: what was typed goes into the
+/// result loaded, and moving to another result loads that one instead.
+pub fn sync_edit(app: &mut App) {
+    let Some(edit) = app.edit.as_mut() else {
+        return;
+    };
+
+    if let Some(result) = app.search.result.get_mut(edit.row) {
+        let text = app.search.query.iter().collect::<String>();
+        if text != result.text {
+            edit.originals
+                .entry(edit.row)
+                .or_insert_with(|| result.text.clone());
+            result.text = text;
+            // The offsets are for the text that was searched.
+            result.submatches.clear();
         }
-        _ => return Ok(()),
+    }
+
+    if edit.row != app.search.scroll {
+        edit.row = app.search.scroll;
+        let text = app.search.result.get(edit.row).map(|r| r.text.as_str());
+        app.search.query = text.unwrap_or_default().chars().collect();
+        app.search.cursor = app
+            .search
+            .cursor
+            .min(app.search.query.len().saturating_sub(1));
+        // Undoing mustn't bring another result's text back.
+        app.history = History::default();
+    }
+}
+
+/// Ends editing, putting the query back and searching for it again.
+fn stop_editing(app: &mut App) -> anyhow::Result<()> {
+    let Some(edit) = app.edit.take() else {
+        return Ok(());
     };
+    app.search.query = edit.query;
+    app.search.cursor = edit.cursor;
+    app.search.mode = Mode::Normal;
+    app.history = History::default();
+
+    get_results(app)
+}
+
+/// Writes every edited result back to its line, unless one of the files changed since
+/// the search. Returns whether editing is over.
+fn write_edits(app: &mut App) -> anyhow::Result<bool> {
+    let Some(edit) = &app.edit else {
+        return Ok(true);
+    };
+
+    let patches = edit.patches(&app.search.result);
+    if patches.is_empty() {
+        stop_editing(app)?;
+        return Ok(true);
+    }
+    match patch::write(&patches) {
+        Ok(written) => {
+            let message = format!(
+                "wrote {}",
+                patch::lines_in_files(written.lines, written.files)
+            );
+            app.message = Some(record_changes(
+                app,
+                ":w of edited results",
+                &patches,
+                message,
+            ));
+            stop_editing(app)?;
+            Ok(true)
+        }
+        Err(failed) => {
+            // Results in files already replaced now read what's on disk.
+            if let Some(edit) = app.edit.as_mut() {
+                let results = &app.search.result;
+                edit.originals.retain(|&row, _| {
+                    results
+                        .get(row)
+                        .is_none_or(|result| !failed.written.paths.contains(&result.path))
+                });
+            }
+            let (Message::Info(message) | Message::Error(message)) =
+                record_failure(app, ":w of edited results", &patches, failed);
+            app.message = Some(Message::Error(format!(
+                "{}, :edit! to discard the edits",
+                message
+            )));
+            Ok(false)
+        }
+    }
+}
+
+/// Records `patches`, just written by `command`, in the undo journal, and tells how
+/// that went along with `message`.
+pub fn record_changes(
+    app: &mut App,
+    command: &str,
+    patches: &[FilePatch],
+    message: String,
+) -> Message {
+    match app.journal.record(command, patches) {
+        Ok(()) => Message::Info(message),
+        Err(e) => Message::Error(format!("{}, but the journal failed: {}", message, e)),
+    }
+}
+
+/// Records the files a failed write of `patches` still replaced in the undo journal, and
+/// tells what went wrong.
+pub fn record_failure(
+    app: &mut App,
+    command: &str,
+    patches: &[FilePatch],
+    failed: patch::Failed,
+) -> Message {
+    let written = patches
+        .iter()
+        .filter(|patch| failed.written.paths.contains(&patch.path))
+        .cloned()
+        .collect::<Vec<_>>();
+    match app.journal.record(command, &written) {
+        Ok(()) => Message::Error(failed.to_string()),
+        Err(e) => Message::Error(format!("{}, and the journal failed: {}", failed, e)),
+    }
+}
+
+/// `:undo-files`, or Enter in `:journal`: puts back the lines a batch of changes
+/// replaced, the newest not yet undone unless `row` says which.
+pub fn undo_files(app: &mut App, row: Option<usize>) -> anyhow::Result<()> {
+    app.message = Some(match app.journal.undo(row) {
+        Ok((batch, written)) => Message::Info(format!(
+            "reverted {} changed by {}",
+            patch::lines_in_files(written.lines, written.files),
+            batch.command
+        )),
+        Err(e) => Message::Error(e),
+    });
+
+    get_results(app)
+}
+
+/// `:w`, writing edited results back to their files, and saving the session and any
+/// settings changed since the last one.
+fn write(app: &mut App) -> anyhow::Result<()> {
+    if !write_edits(app)? {
+        return Ok(());
+    }
+    app.save()?;
+    if app.config.changed.is_empty() {
+        return Ok(());
+    }
+
+    let persisted = match app.config.persist() {
+        Ok(count) => Message::Info(format!(
+            "wrote {} settings to {}",
+            count,
+            Config::path()?.display()
+        )),
+        Err(e) => Message::Error(format!("config: {}", e)),
+    };
+    // What became of the edits is still told, and either failing makes it an error.
+    app.message = Some(match (app.message.take(), persisted) {
+        (None, persisted) => persisted,
+        (Some(Message::Info(edits)), Message::Info(settings)) => {
+            Message::Info(format!("{}; {}", edits, settings))
+        }
+        (
+            Some(Message::Info(edits) | Message::Error(edits)),
+            Message::Info(settings) | Message::Error(settings),
+        ) => Message::Error(format!("{}; {}", edits, settings)),
+    });
 
-    app.vi_command.clear();
     Ok(())
 }
 
-pub fn handle_exit_commands(app: &mut App) -> anyhow::Result<()> {
-    match app.command.query.iter().collect::<String>().as_ref() {
-        ":q" => {
-            app.command.query.clear();
-            app.quit();
+/// Runs the ex command on the command line, reporting unknown or malformed ones.
+pub fn handle_exit_commands(
+    app: &mut App,
+    tui: &mut Tui<CrosstermBackend<io::Stderr>>,
+) -> anyhow::Result<()> {
+    let line = app.command.query.iter().collect::<String>();
+    if line.trim_start_matches(':').trim().is_empty() {
+        return Ok(());
+    }
+    let ex = match ex::parse(&line) {
+        Ok(ex) => ex,
+        Err(e) => {
+            app.message = Some(Message::Error(e));
+            return Ok(());
+        }
+    };
+
+    match ex.name {
+        "cexport" => {
+            let path = match ex.args.as_slice() {
+                [] => quickfix_path(),
+                [path] => PathBuf::from(path),
+                _ => {
+                    app.message = Some(Message::Error("cexport takes one file".to_string()));
+                    return Ok(());
+                }
+            };
+            app.message = Some(match write_quickfix(app, &path) {
+                Ok(count) => {
+                    Message::Info(format!("wrote {} entries to {}", count, path.display()))
+                }
+                Err(e) => Message::Error(e.to_string()),
+            });
+        }
+        "copen" => {
+            if let Err(e) = open_quickfix(app, tui) {
+                app.message = Some(Message::Error(e.to_string()));
+            }
+        }
+        "set" => set(app, &ex.args)?,
+        "options" => app.window = Window::Options,
+        "s" => substitute(app, &ex),
+        "edit" if ex.bang && app.edit.is_none() => {
+            app.message = Some(Message::Error("not editing the results".to_string()));
         }
-        ":w" => {
-            app.command.query.clear();
-            app.save()?;
+        "edit" if ex.bang => {
+            stop_editing(app)?;
+            app.message = Some(Message::Info("discarded the edits".to_string()));
         }
-        ":wq" => {
-            app.command.query.clear();
-            app.save()?;
-            app.quit()
+        "edit" => start_editing(app),
+        "journal" if app.journal.is_empty() => {
+            app.message = Some(Message::Info("the journal is empty".to_string()));
         }
-        ":q!" => {
+        "journal" => {
+            app.journal.selected = 0;
+            app.window = Window::Journal;
+        }
+        "undo-files" => undo_files(app, None)?,
+        "q" | "quit" if ex.bang => {
             app.delete_session()?;
             app.quit();
         }
+        "q" | "quit" if is_modified(app) => {
+            app.message = Some(Message::Error(
+                "edited results not written, :w to write or :q! to quit anyway".to_string(),
+            ));
+        }
+        "q" | "quit" => app.quit(),
+        "w" | "write" => write(app)?,
+        "wq" | "x" => {
+            write(app)?;
+            if app.edit.is_none() {
+                app.quit()
+            }
+        }
         _ => {}
     }
 
     Ok(())
 }
+
+/// Whether there are edited results that haven't been written.
+fn is_modified(app: &App) -> bool {
+    app.edit
+        .as_ref()
+        .is_some_and(|edit| edit.is_modified(&app.search.result))
+}
@@ -0,0 +1,212 @@
+use crate::{app::config_dir, fuzzy};
+use std::{cmp::Reverse, fs, ops::Range, path::PathBuf};
+
+/// Lines that were entered, oldest first, kept one per line in a file under
+/// `~/.config/lens`: searched queries in `history` and ex commands in `command_history`.
+#[derive(Debug, Default)]
+pub struct InputHistory {
+    entries: Vec<String>,
+    /// Where the history is kept, `None` for one that only lives in memory.
+    file: Option<PathBuf>,
+    limit: usize,
+    /// The entry being recalled with Up and Down, and what was typed before recalling.
+    recall: Option<(usize, String)>,
+    /// What's typed into the Ctrl-r popup.
+    pub filter: String,
+    /// The highlighted row of the popup.
+    pub selected: usize,
+}
+
+impl InputHistory {
+    /// Reads the history file called `name`, keeping at most the `limit` newest entries.
+    /// A missing file is an empty history.
+    pub fn load(name: &str, limit: usize) -> Self {
+        match config_dir() {
+            Ok(dir) => Self::read(dir.join(name), limit),
+            Err(_) => Self {
+                limit,
+                ..Self::default()
+            },
+        }
+    }
+
+    fn read(file: PathBuf, limit: usize) -> Self {
+        let entries = fs::read_to_string(&file)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let skip = entries.len().saturating_sub(limit);
+
+        Self {
+            entries: entries.into_iter().skip(skip).collect(),
+            file: Some(file),
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// Adds `query` as the newest entry, dropping an older copy of it and the oldest
+    /// entries past the limit, and writes the history out.
+    pub fn record(&mut self, query: &str) -> anyhow::Result<()> {
+        if query.is_empty() || query.contains('\n') || self.limit == 0 {
+            return Ok(());
+        }
+        if self.entries.last().is_some_and(|last| last == query) {
+            return Ok(());
+        }
+
+        self.recall = None;
+        self.entries.retain(|entry| entry != query);
+        self.entries.push(query.to_string());
+        let excess = self.entries.len().saturating_sub(self.limit);
+        self.entries.drain(..excess);
+
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut contents = self.entries.join("\n");
+        contents.push('\n');
+        fs::write(path, contents)?;
+
+        Ok(())
+    }
+
+    /// The entry recalled right now, as long as `current` hasn't been edited since.
+    fn recalled(&self, current: &str) -> Option<&(usize, String)> {
+        self.recall
+            .as_ref()
+            .filter(|(index, _)| self.entries.get(*index).is_some_and(|e| e == current))
+    }
+
+    /// The next older entry starting with what was typed before recalling, like Up in a
+    /// shell. Editing a recalled query starts over from the edited text.
+    pub fn older(&mut self, current: &str) -> Option<String> {
+        let (index, typed) = match self.recalled(current) {
+            Some((index, typed)) => (*index, typed.clone()),
+            None => (self.entries.len(), current.to_string()),
+        };
+        let index = (0..index)
+            .rev()
+            .find(|&i| self.entries[i].starts_with(&typed) && self.entries[i] != current)?;
+
+        self.recall = Some((index, typed));
+        Some(self.entries[index].clone())
+    }
+
+    /// The next newer entry, or what was typed before recalling once past the newest.
+    pub fn newer(&mut self, current: &str) -> Option<String> {
+        let (index, typed) = self.recalled(current)?.clone();
+        let newer = (index + 1..self.entries.len())
+            .find(|&i| self.entries[i].starts_with(&typed) && self.entries[i] != current);
+
+        match newer {
+            Some(index) => {
+                self.recall = Some((index, typed));
+                Some(self.entries[index].clone())
+            }
+            None => {
+                self.recall = None;
+                Some(typed)
+            }
+        }
+    }
+
+    /// Entries matching the popup filter, best first and newest first among equals, with
+    /// the byte ranges that matched.
+    pub fn matches(&self) -> Vec<(&str, Vec<Range<usize>>)> {
+        let mut scorer = fuzzy::Scorer::new(&self.filter);
+        let mut matches = self
+            .entries
+            .iter()
+            .rev()
+            .filter_map(|entry| {
+                let (score, ranges) = scorer.matches(entry)?;
+                Some((score, entry.as_str(), ranges))
+            })
+            .collect::<Vec<_>>();
+        matches.sort_by_key(|(score, _, _)| Reverse(*score));
+
+        matches
+            .into_iter()
+            .map(|(_, entry, ranges)| (entry, ranges))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// A history kept in a fresh directory, along with its file.
+    fn history(name: &str, limit: usize) -> (InputHistory, PathBuf) {
+        let dir = env::temp_dir().join(format!("lens-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let file = dir.join("history");
+        (InputHistory::read(file.clone(), limit), file)
+    }
+
+    fn record(history: &mut InputHistory, queries: &[&str]) {
+        for query in queries {
+            history.record(query).unwrap();
+        }
+    }
+
+    #[test]
+    fn records_without_duplicates_up_to_the_limit() {
+        let (mut history, file) = history("record", 3);
+        record(&mut history, &["a", "b", "a", "", "c", "c", "d"]);
+        assert_eq!(history.entries, ["a", "c", "d"]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "a\nc\nd\n");
+        assert_eq!(InputHistory::read(file.clone(), 2).entries, ["c", "d"]);
+
+        fs::remove_dir_all(file.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn records_nothing_without_room() {
+        let (mut history, file) = history("empty", 0);
+        record(&mut history, &["a"]);
+        assert!(history.entries.is_empty());
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn recalls_entries_starting_with_what_was_typed() {
+        let mut history = InputHistory {
+            limit: 10,
+            ..InputHistory::default()
+        };
+        record(&mut history, &["foo", "bar", "fob", "fizz"]);
+
+        assert_eq!(history.older("fo").as_deref(), Some("fob"));
+        assert_eq!(history.older("fob").as_deref(), Some("foo"));
+        assert_eq!(history.older("foo"), None);
+        assert_eq!(history.newer("foo").as_deref(), Some("fob"));
+        assert_eq!(history.newer("fob").as_deref(), Some("fo"));
+        assert_eq!(history.newer("fo"), None);
+
+        // Editing a recalled entry starts over from the edit.
+        assert_eq!(history.older("").as_deref(), Some("fizz"));
+        assert_eq!(history.older("b").as_deref(), Some("bar"));
+    }
+
+    #[test]
+    fn ranks_matches_best_then_newest() {
+        let mut history = InputHistory {
+            limit: 10,
+            ..InputHistory::default()
+        };
+        record(&mut history, &["xfoo", "foo bar", "foo baz", "nothing"]);
+        history.filter = "foo".to_string();
+
+        let matches = history.matches();
+        let entries = matches.iter().map(|(entry, _)| *entry).collect::<Vec<_>>();
+        assert_eq!(entries, ["foo baz", "foo bar", "xfoo"]);
+        assert_eq!(matches[0].1, vec![0..3]);
+    }
+}
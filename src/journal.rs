@@ -0,0 +1,252 @@
+use crate::{
+    app::config_dir,
+    patch::{self, FilePatch, Hunk, Written},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many batches the journal keeps.
+const LIMIT: usize = 100;
+
+/// The lines one command changed, with what they read before.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Batch {
+    /// Seconds since the epoch.
+    pub time: u64,
+    /// What made the changes, like `:%s/foo/bar/g`.
+    pub command: String,
+    pub patches: Vec<FilePatch>,
+    pub undone: bool,
+}
+
+impl Batch {
+    pub fn lines(&self) -> usize {
+        self.patches.iter().map(|patch| patch.hunks.len()).sum()
+    }
+}
+
+/// Every batch of file changes made from lens, oldest first, kept one per line as JSON
+/// in `~/.config/lens/journal` so they can still be taken back after a restart.
+#[derive(Debug, Default)]
+pub struct Journal {
+    batches: Vec<Batch>,
+    /// The highlighted row of `:journal`, which lists the newest first.
+    pub selected: usize,
+    /// Where the journal is kept, `None` for one that only lives in memory.
+    file: Option<PathBuf>,
+}
+
+impl Journal {
+    fn path() -> anyhow::Result<PathBuf> {
+        Ok(config_dir()?.join("journal"))
+    }
+
+    /// Reads the journal, skipping lines it can't make sense of. A missing file is an
+    /// empty journal.
+    pub fn load() -> Self {
+        Self::path().map(Self::read).unwrap_or_default()
+    }
+
+    fn read(file: PathBuf) -> Self {
+        let batches = fs::read_to_string(&file)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+
+        Self {
+            batches,
+            selected: 0,
+            file: Some(file),
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut contents = String::new();
+        for batch in &self.batches {
+            contents.push_str(&serde_json::to_string(batch)?);
+            contents.push('\n');
+        }
+        fs::write(path, contents)?;
+
+        Ok(())
+    }
+
+    /// The batches, newest first.
+    pub fn batches(&self) -> impl Iterator<Item = &Batch> {
+        self.batches.iter().rev()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Records the accepted hunks of `patches`, which `command` has just written,
+    /// dropping the oldest batches past the limit.
+    pub fn record(&mut self, command: &str, patches: &[FilePatch]) -> anyhow::Result<()> {
+        let patches = patches
+            .iter()
+            .map(|patch| FilePatch {
+                hunks: patch.hunks.iter().filter(|h| h.accepted).cloned().collect(),
+                ..patch.clone()
+            })
+            .filter(|patch| !patch.hunks.is_empty())
+            .collect::<Vec<_>>();
+        if patches.is_empty() {
+            return Ok(());
+        }
+
+        self.batches.push(Batch {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            command: command.to_string(),
+            patches,
+            undone: false,
+        });
+        let excess = self.batches.len().saturating_sub(LIMIT);
+        self.batches.drain(..excess);
+        self.selected = 0;
+
+        self.save()
+    }
+
+    /// Puts back what the batch `row` rows down from the newest replaced, or with `None`
+    /// the newest batch that hasn't been undone. Nothing is written unless every line
+    /// still reads what the batch left it as.
+    pub fn undo(&mut self, row: Option<usize>) -> Result<(&Batch, Written), String> {
+        let index = match row {
+            Some(row) => self.batches.len().checked_sub(row + 1),
+            None => self.batches.iter().rposition(|batch| !batch.undone),
+        }
+        .ok_or_else(|| "nothing to undo".to_string())?;
+        let batch = &self.batches[index];
+        if batch.undone {
+            return Err(format!("{} was already undone", batch.command));
+        }
+
+        let reverted = batch
+            .patches
+            .iter()
+            .map(|patch| FilePatch {
+                path: patch.path.clone(),
+                hunks: patch
+                    .hunks
+                    .iter()
+                    .map(|hunk| Hunk {
+                        line: hunk.line,
+                        before: hunk.after.clone(),
+                        after: hunk.before.clone(),
+                        accepted: true,
+                    })
+                    .collect(),
+                unchanged_since: None,
+            })
+            .collect::<Vec<_>>();
+        let written = match patch::write(&reverted) {
+            Ok(written) => written,
+            Err(failed) => {
+                // Whatever was put back is off the batch, so undoing it again only
+                // retries the rest.
+                if failed.written.files > 0 {
+                    let paths = &failed.written.paths;
+                    self.batches[index]
+                        .patches
+                        .retain(|patch| !paths.contains(&patch.path));
+                    let _ = self.save();
+                }
+                return Err(failed.to_string());
+            }
+        };
+
+        self.batches[index].undone = true;
+        self.save().map_err(|e| format!("journal: {}", e))?;
+        Ok((&self.batches[index], written))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::Path};
+
+    fn replace(path: &Path, line: usize, before: &str, after: &str) -> FilePatch {
+        FilePatch {
+            path: path.to_path_buf(),
+            hunks: vec![Hunk {
+                line,
+                before: before.to_string(),
+                after: after.to_string(),
+                accepted: true,
+            }],
+            unchanged_since: None,
+        }
+    }
+
+    #[test]
+    fn undoes_a_batch_and_remembers_it() {
+        let dir = env::temp_dir().join(format!("lens-journal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (file, journal) = (dir.join("file"), dir.join("journal"));
+        fs::write(&file, "foo\nbar\n").unwrap();
+
+        let patches = [replace(&file, 2, "bar", "baz")];
+        patch::write(&patches).unwrap();
+        Journal::read(journal.clone())
+            .record(":s/bar/baz/", &patches)
+            .unwrap();
+
+        let mut reloaded = Journal::read(journal.clone());
+        assert_eq!(reloaded.batches().count(), 1);
+        let (batch, written) = reloaded.undo(None).unwrap();
+        assert_eq!((batch.command.as_str(), written.lines), (":s/bar/baz/", 1));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "foo\nbar\n");
+        assert_eq!(reloaded.undo(None).unwrap_err(), "nothing to undo");
+
+        let mut reloaded = Journal::read(journal.clone());
+        assert!(reloaded.batches().all(|batch| batch.undone));
+        assert_eq!(
+            reloaded.undo(Some(0)).unwrap_err(),
+            ":s/bar/baz/ was already undone"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn leaves_lines_changed_since_alone() {
+        let dir = env::temp_dir().join(format!("lens-journal-conflict-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file");
+        fs::write(&file, "foo\n").unwrap();
+
+        let mut journal = Journal::default();
+        let patches = [replace(&file, 1, "foo", "bar")];
+        patch::write(&patches).unwrap();
+        journal.record(":s/foo/bar/", &patches).unwrap();
+        fs::write(&file, "qux\n").unwrap();
+
+        let error = journal.undo(None).unwrap_err();
+        assert!(
+            error.ends_with("has changed on disk, nothing written"),
+            "{}",
+            error
+        );
+        assert_eq!(fs::read_to_string(&file).unwrap(), "qux\n");
+        assert!(journal.batches().all(|batch| !batch.undone));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,652 @@
+use crate::vi::{Find, Motion, Operator};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{de::IntoDeserializer, Deserialize};
+use std::{collections::HashMap, fmt};
+
+/// A key press with the modifiers that matter for mappings. Shift is folded into the
+/// character itself, so `G` is just `G`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+                KeyCode::Char(c.to_ascii_lowercase())
+            }
+            code => code,
+        };
+
+        Self { code, modifiers }
+    }
+
+    /// A plain character typed without Ctrl or Alt, as text input wants it.
+    pub fn char(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(c) if self.modifiers.is_empty() => Some(c),
+            _ => None,
+        }
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+}
+
+/// Prints keys the way they are written in mappings, e.g. `<C-f>` or `<Space>`.
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char('<') => "lt".to_string(),
+            KeyCode::Char(c) if self.modifiers.is_empty() => return write!(f, "{}", c),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Enter => "CR".to_string(),
+            KeyCode::Esc => "Esc".to_string(),
+            KeyCode::Tab => "Tab".to_string(),
+            KeyCode::BackTab => "S-Tab".to_string(),
+            KeyCode::Backspace => "BS".to_string(),
+            KeyCode::Delete => "Del".to_string(),
+            KeyCode::Insert => "Insert".to_string(),
+            KeyCode::Up => "Up".to_string(),
+            KeyCode::Down => "Down".to_string(),
+            KeyCode::Left => "Left".to_string(),
+            KeyCode::Right => "Right".to_string(),
+            KeyCode::Home => "Home".to_string(),
+            KeyCode::End => "End".to_string(),
+            KeyCode::PageUp => "PageUp".to_string(),
+            KeyCode::PageDown => "PageDown".to_string(),
+            KeyCode::F(n) => format!("F{}", n),
+            code => format!("{:?}", code),
+        };
+
+        let mut prefix = String::new();
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            prefix.push_str("C-");
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            prefix.push_str("A-");
+        }
+        write!(f, "<{}{}>", prefix, name)
+    }
+}
+
+/// Parses a vim-style key sequence such as `gg`, `<C-f>` or `<leader>f`.
+pub fn parse_keys(text: &str, leader: &[Key]) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let special = match c {
+            '<' => rest[1..].find('>').map(|end| &rest[1..end + 1]),
+            _ => None,
+        };
+        match special {
+            Some(name) => {
+                match name.to_ascii_lowercase().as_ref() {
+                    "leader" => keys.extend_from_slice(leader),
+                    _ => keys.push(parse_special(name).ok_or(format!("unknown key <{}>", name))?),
+                }
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                keys.push(Key::new(KeyCode::Char(c), KeyModifiers::NONE));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    if keys.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+
+    Ok(keys)
+}
+
+/// The inside of a `<...>` key, with optional `C-`, `A-`/`M-` and `S-` prefixes.
+fn parse_special(name: &str) -> Option<Key> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut name = name;
+    while let Some((modifier, rest)) = name.split_once('-').filter(|(_, rest)| !rest.is_empty()) {
+        modifiers |= match modifier.to_ascii_lowercase().as_ref() {
+            "c" => KeyModifiers::CONTROL,
+            "a" | "m" => KeyModifiers::ALT,
+            "s" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        name = rest;
+    }
+
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+            KeyCode::Char(c.to_ascii_uppercase())
+        }
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match name.to_ascii_lowercase().as_ref() {
+            "cr" | "enter" | "return" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            "tab" => KeyCode::Tab,
+            "bs" | "backspace" => KeyCode::Backspace,
+            "space" => KeyCode::Char(' '),
+            "lt" => KeyCode::Char('<'),
+            "bar" => KeyCode::Char('|'),
+            "bslash" => KeyCode::Char('\\'),
+            "del" | "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            name => KeyCode::F(name.strip_prefix('f')?.parse().ok()?),
+        },
+    };
+
+    Some(Key::new(code, modifiers))
+}
+
+/// Where a key is pressed: the search box in one of its modes, or the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Context {
+    Normal,
+    Insert,
+    Visual,
+    /// After an operator like `d`, waiting for its motion or text object.
+    Operator,
+    Command,
+}
+
+/// Everything a key can be mapped to, named in kebab-case in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    ToggleMatcher,
+    ToggleSource,
+    MarkAll,
+    FirstResult,
+    LastResult,
+    PreviousResult,
+    NextResult,
+    /// Opens the selection, runs the command line, or prints the selection in `--filter`.
+    Accept,
+    ToggleMark,
+    InvertMarks,
+    VisualLine,
+    MarkVisual,
+    /// Leaves insert or visual mode, or cancels the command line.
+    NormalMode,
+    Insert,
+    Append,
+    InsertStart,
+    AppendEnd,
+    CursorLeft,
+    CursorRight,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    BigWordForward,
+    BigWordBackward,
+    BigWordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    /// `f`, `F`, `t` and `T`, which take the character to look for.
+    FindForward,
+    FindBackward,
+    TillForward,
+    TillBackward,
+    /// `;` and `,`
+    RepeatFind,
+    RepeatFindReverse,
+    /// `i` and `a` after an operator or in visual mode, which take the text object.
+    Inner,
+    Around,
+    /// The `d` operator, waiting for a motion.
+    Delete,
+    /// The `c` operator, waiting for a motion.
+    Change,
+    /// The `y` operator, waiting for a motion.
+    Yank,
+    DeleteChar,
+    DeleteToEnd,
+    YankLine,
+    /// `s`, `S` and `C`
+    Substitute,
+    SubstituteLine,
+    ChangeToEnd,
+    /// `r`, which takes the replacement character.
+    ReplaceChar,
+    ReplaceMode,
+    Visual,
+    /// `o` in visual mode, moving the cursor to the other end of the selection.
+    SwapEnds,
+    PutAfter,
+    PutBefore,
+    /// `"`, which takes the name of the register for the next yank, delete or put.
+    Register,
+    Undo,
+    Redo,
+    /// `.`, repeating the last change to the query.
+    Repeat,
+    Backspace,
+    /// Up and Down while typing, recalling past queries or moving through the history
+    /// popup.
+    OlderQuery,
+    NewerQuery,
+    /// Ctrl-r while typing, opening the history popup.
+    SearchHistory,
+    /// Tab and Shift-Tab on the command line.
+    Complete,
+    CompletePrevious,
+    CommandLine,
+    /// Opens or closes the options panel.
+    Options,
+}
+
+impl Action {
+    pub fn motion(self) -> Option<Motion> {
+        match self {
+            Action::CursorLeft => Some(Motion::Left),
+            Action::CursorRight => Some(Motion::Right),
+            Action::WordForward => Some(Motion::WordForward),
+            Action::WordBackward => Some(Motion::WordBackward),
+            Action::WordEnd => Some(Motion::WordEnd),
+            Action::BigWordForward => Some(Motion::BigWordForward),
+            Action::BigWordBackward => Some(Motion::BigWordBackward),
+            Action::BigWordEnd => Some(Motion::BigWordEnd),
+            Action::LineStart => Some(Motion::LineStart),
+            Action::FirstNonBlank => Some(Motion::FirstNonBlank),
+            Action::LineEnd => Some(Motion::LineEnd),
+            _ => None,
+        }
+    }
+
+    /// Whether the action needs the next typed character to mean anything.
+    pub fn takes_char(self) -> bool {
+        self.find(' ').is_some()
+            || matches!(
+                self,
+                Action::Inner | Action::Around | Action::Register | Action::ReplaceChar
+            )
+    }
+
+    /// The character search an `f`, `F`, `t` or `T` for `char` stands for.
+    pub fn find(self, char: char) -> Option<Find> {
+        let (backward, till) = match self {
+            Action::FindForward => (false, false),
+            Action::FindBackward => (true, false),
+            Action::TillForward => (false, true),
+            Action::TillBackward => (true, true),
+            _ => return None,
+        };
+
+        Some(Find {
+            char,
+            backward,
+            till,
+        })
+    }
+
+    pub fn operator(self) -> Option<Operator> {
+        match self {
+            Action::Delete => Some(Operator::Delete),
+            Action::Change => Some(Operator::Change),
+            Action::Yank => Some(Operator::Yank),
+            _ => None,
+        }
+    }
+
+    /// Actions that are shorthand for an operator and a motion, like `x` for `dl`. No
+    /// motion means the whole query, like `Y` for `yy`.
+    pub fn shorthand(self) -> Option<(Operator, Option<Motion>)> {
+        match self {
+            Action::DeleteChar => Some((Operator::Delete, Some(Motion::Right))),
+            Action::DeleteToEnd => Some((Operator::Delete, Some(Motion::LineEnd))),
+            Action::YankLine => Some((Operator::Yank, None)),
+            Action::Substitute => Some((Operator::Change, Some(Motion::Right))),
+            Action::SubstituteLine => Some((Operator::Change, None)),
+            Action::ChangeToEnd => Some((Operator::Change, Some(Motion::LineEnd))),
+            _ => None,
+        }
+    }
+}
+
+/// What the keys typed so far amount to.
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    Action(Action),
+    /// A prefix of a longer mapping; wait for more keys.
+    Pending,
+    None,
+}
+
+/// Key sequences mapped to actions, per context.
+#[derive(Debug, Clone)]
+pub struct Keymap(HashMap<Context, HashMap<Vec<Key>, Action>>);
+
+impl Default for Keymap {
+    fn default() -> Self {
+        use Action::*;
+        use Context::{Command as C, Insert as I, Normal as N, Operator as O, Visual as V};
+
+        let bindings: [(&[Context], &[&str], Action); 69] = [
+            (&[N, I, V], &["<C-f>"], ToggleMatcher),
+            (&[N, I, V], &["<C-t>"], ToggleSource),
+            (&[N, I, V], &["<C-a>"], MarkAll),
+            (&[N, I, V, C], &["<CR>"], Accept),
+            (&[N, V], &["<Up>"], PreviousResult),
+            (&[N, V], &["<Down>"], NextResult),
+            (&[I, C], &["<Up>", "<C-p>"], OlderQuery),
+            (&[I, C], &["<Down>", "<C-n>"], NewerQuery),
+            (&[I], &["<C-r>"], SearchHistory),
+            (&[C], &["<Left>"], CursorLeft),
+            (&[C], &["<Right>"], CursorRight),
+            (&[C], &["<Home>", "<C-b>"], LineStart),
+            (&[C], &["<End>", "<C-e>"], LineEnd),
+            (&[C], &["<Tab>"], Complete),
+            (&[C], &["<S-Tab>"], CompletePrevious),
+            (&[N, V], &["k"], PreviousResult),
+            (&[N, V], &["j"], NextResult),
+            (&[N, V], &["gg"], FirstResult),
+            (&[N, V], &["G"], LastResult),
+            (&[N], &["<Tab>", "<Space>"], ToggleMark),
+            (&[N], &["~"], InvertMarks),
+            (&[N], &["V"], VisualLine),
+            (&[V], &["<Tab>", "<Space>", "V"], MarkVisual),
+            (&[N, I, V, C], &["<Esc>"], NormalMode),
+            (&[N, I, V], &["<C-o>"], Options),
+            (&[N], &["i"], Insert),
+            (&[N], &["a"], Append),
+            (&[N], &["I"], InsertStart),
+            (&[N], &["A"], AppendEnd),
+            (&[N, O, V], &["h"], CursorLeft),
+            (&[N, O, V], &["l"], CursorRight),
+            (&[N, O, V], &["w"], WordForward),
+            (&[N, O, V], &["b"], WordBackward),
+            (&[N, O, V], &["e"], WordEnd),
+            (&[N, O, V], &["W"], BigWordForward),
+            (&[N, O, V], &["B"], BigWordBackward),
+            (&[N, O, V], &["E"], BigWordEnd),
+            (&[N, O, V], &["0"], LineStart),
+            (&[N, O, V], &["^"], FirstNonBlank),
+            (&[N, O, V], &["$"], LineEnd),
+            (&[N, O, V], &["f"], FindForward),
+            (&[N, O, V], &["F"], FindBackward),
+            (&[N, O, V], &["t"], TillForward),
+            (&[N, O, V], &["T"], TillBackward),
+            (&[N, O, V], &[";"], RepeatFind),
+            (&[N, O, V], &[","], RepeatFindReverse),
+            (&[O, V], &["i"], Inner),
+            (&[O, V], &["a"], Around),
+            (&[N, O, V], &["d"], Delete),
+            (&[N, O, V], &["c"], Change),
+            (&[N, O, V], &["y"], Yank),
+            (&[N], &["Y"], YankLine),
+            (&[N], &["p"], PutAfter),
+            (&[N], &["P"], PutBefore),
+            (&[N, V], &["\""], Register),
+            (&[N], &["u"], Undo),
+            (&[N], &["<C-r>"], Redo),
+            (&[N], &["."], Repeat),
+            (&[N, V], &["x"], DeleteChar),
+            (&[N, V], &["s"], Substitute),
+            (&[N], &["S"], SubstituteLine),
+            (&[N], &["C"], ChangeToEnd),
+            (&[N, V], &["r"], ReplaceChar),
+            (&[N], &["R"], ReplaceMode),
+            (&[N, V], &["v"], Visual),
+            (&[V], &["o"], SwapEnds),
+            (&[N], &["D"], DeleteToEnd),
+            (&[I, C], &["<BS>"], Backspace),
+            (&[N], &[":"], CommandLine),
+        ];
+
+        let mut keymap = Self(HashMap::new());
+        for (contexts, sequences, action) in bindings {
+            for context in contexts {
+                for sequence in sequences {
+                    let keys = parse_keys(sequence, &[]).expect("default bindings parse");
+                    keymap.0.entry(*context).or_default().insert(keys, action);
+                }
+            }
+        }
+
+        keymap
+    }
+}
+
+impl Keymap {
+    pub fn lookup(&self, context: Context, keys: &[Key]) -> Lookup {
+        let Some(bindings) = self.0.get(&context) else {
+            return Lookup::None;
+        };
+        if bindings
+            .keys()
+            .any(|sequence| sequence.len() > keys.len() && sequence.starts_with(keys))
+        {
+            return Lookup::Pending;
+        }
+
+        match bindings.get(keys) {
+            Some(action) => Lookup::Action(*action),
+            None => Lookup::None,
+        }
+    }
+
+    /// The longest mapped prefix of `keys` and its action, for when no mapping can
+    /// extend what was typed.
+    pub fn longest_prefix(&self, context: Context, keys: &[Key]) -> Option<(usize, Action)> {
+        let bindings = self.0.get(&context)?;
+        (1..=keys.len())
+            .rev()
+            .find_map(|len| bindings.get(&keys[..len]).map(|action| (len, *action)))
+    }
+
+    /// Applies one vim-style entry: `nnoremap <leader>f toggle-matcher`, `iunmap <C-a>`.
+    ///
+    /// The `n`, `i`, `v`/`x`, `o` and `c` prefixes pick the context; a bare `map` or
+    /// `unmap` covers normal, visual and operator-pending mode like in vim. Since the
+    /// right-hand side is always an action, `map` and `noremap` mean the same thing.
+    pub fn apply(&mut self, entry: &str, leader: &[Key]) -> Result<(), String> {
+        let words = entry.split_whitespace().collect::<Vec<_>>();
+        let (command, lhs, rhs) = match words.as_slice() {
+            [command, lhs] => (*command, *lhs, None),
+            [command, lhs, rhs] => (*command, *lhs, Some(*rhs)),
+            _ => {
+                return Err(format!(
+                    "expected '<command> <keys> [action]' in '{}'",
+                    entry
+                ))
+            }
+        };
+
+        let (prefix, kind) = ["noremap", "unmap", "map"]
+            .into_iter()
+            .find_map(|kind| command.strip_suffix(kind).map(|prefix| (prefix, kind)))
+            .ok_or(format!("unknown command '{}'", command))?;
+        let contexts: &[Context] = match prefix {
+            "" => &[Context::Normal, Context::Visual, Context::Operator],
+            "n" => &[Context::Normal],
+            "i" => &[Context::Insert],
+            "v" | "x" => &[Context::Visual],
+            "o" => &[Context::Operator],
+            "c" => &[Context::Command],
+            _ => return Err(format!("unknown command '{}'", command)),
+        };
+        let keys = parse_keys(lhs, leader)?;
+
+        match (kind, rhs) {
+            ("unmap", None) => {
+                for context in contexts {
+                    self.0.entry(*context).or_default().remove(&keys);
+                }
+            }
+            ("noremap" | "map", Some(rhs)) => {
+                let action = Action::deserialize(rhs.into_deserializer())
+                    .map_err(|e: serde::de::value::Error| format!("{} in '{}'", e, entry))?;
+                for context in contexts {
+                    self.0
+                        .entry(*context)
+                        .or_default()
+                        .insert(keys.clone(), action);
+                }
+            }
+            _ => return Err(format!("wrong number of arguments in '{}'", entry)),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        Key::new(code, modifiers)
+    }
+
+    fn char(c: char) -> Key {
+        key(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn parses_plain_and_special_keys() {
+        assert_eq!(parse_keys("gg", &[]), Ok(vec![char('g'), char('g')]));
+        assert_eq!(
+            parse_keys("<C-f><CR><S-Tab><Space>", &[]),
+            Ok(vec![
+                key(KeyCode::Char('f'), KeyModifiers::CONTROL),
+                key(KeyCode::Enter, KeyModifiers::NONE),
+                key(KeyCode::BackTab, KeyModifiers::NONE),
+                char(' '),
+            ])
+        );
+        assert_eq!(
+            parse_keys("<M-x><F5><lt>", &[]),
+            Ok(vec![
+                key(KeyCode::Char('x'), KeyModifiers::ALT),
+                key(KeyCode::F(5), KeyModifiers::NONE),
+                char('<'),
+            ])
+        );
+    }
+
+    #[test]
+    fn folds_shift_and_case() {
+        assert_eq!(parse_keys("<S-g>", &[]), Ok(vec![char('G')]));
+        assert_eq!(
+            parse_keys("<C-F>", &[]),
+            parse_keys("<c-f>", &[]),
+            "Ctrl ignores case like in vim"
+        );
+        assert_eq!(
+            Key::from(KeyEvent::new(KeyCode::Char('G'), KeyModifiers::SHIFT)),
+            char('G')
+        );
+    }
+
+    #[test]
+    fn expands_the_leader() {
+        let leader = [char(',')];
+        assert_eq!(
+            parse_keys("<leader>f", &leader),
+            Ok(vec![char(','), char('f')])
+        );
+    }
+
+    #[test]
+    fn rejects_bad_sequences() {
+        assert!(parse_keys("", &[]).is_err());
+        assert_eq!(
+            parse_keys("<bogus>", &[]),
+            Err("unknown key <bogus>".to_string())
+        );
+        assert!(parse_keys("<X-a>", &[]).is_err());
+        // An unclosed `<` is just the character.
+        assert_eq!(parse_keys("<a", &[]), Ok(vec![char('<'), char('a')]));
+    }
+
+    #[test]
+    fn prints_keys_back_as_written() {
+        for text in ["<C-f>", "<CR>", "<Space>", "<lt>", "G", "<A-x>"] {
+            let keys = parse_keys(text, &[]).unwrap();
+            assert_eq!(keys[0].to_string(), text);
+        }
+    }
+
+    #[test]
+    fn looks_up_mappings_and_prefixes() {
+        let keymap = Keymap::default();
+        let g = parse_keys("g", &[]).unwrap();
+        let gg = parse_keys("gg", &[]).unwrap();
+        assert_eq!(keymap.lookup(Context::Normal, &g), Lookup::Pending);
+        assert_eq!(
+            keymap.lookup(Context::Normal, &gg),
+            Lookup::Action(Action::FirstResult)
+        );
+        assert_eq!(keymap.lookup(Context::Insert, &gg), Lookup::None);
+        let ggx = parse_keys("ggx", &[]).unwrap();
+        assert_eq!(
+            keymap.longest_prefix(Context::Normal, &ggx),
+            Some((2, Action::FirstResult))
+        );
+    }
+
+    #[test]
+    fn applies_and_removes_mappings() {
+        let mut keymap = Keymap::default();
+        let leader = [char(' ')];
+        keymap
+            .apply("nnoremap <leader>m mark-all", &leader)
+            .unwrap();
+        let keys = parse_keys("<Space>m", &[]).unwrap();
+        assert_eq!(
+            keymap.lookup(Context::Normal, &keys),
+            Lookup::Action(Action::MarkAll)
+        );
+        assert_eq!(keymap.lookup(Context::Visual, &keys), Lookup::None);
+
+        // A bare `map` covers normal, visual and operator-pending mode.
+        keymap.apply("map Q mark-all", &leader).unwrap();
+        let q = parse_keys("Q", &[]).unwrap();
+        for context in [Context::Normal, Context::Visual, Context::Operator] {
+            assert_eq!(keymap.lookup(context, &q), Lookup::Action(Action::MarkAll));
+        }
+        assert_eq!(keymap.lookup(Context::Insert, &q), Lookup::None);
+
+        keymap.apply("iunmap <C-a>", &leader).unwrap();
+        let ctrl_a = parse_keys("<C-a>", &[]).unwrap();
+        assert_eq!(keymap.lookup(Context::Insert, &ctrl_a), Lookup::None);
+        assert_eq!(
+            keymap.lookup(Context::Normal, &ctrl_a),
+            Lookup::Action(Action::MarkAll)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_entries() {
+        let mut keymap = Keymap::default();
+        for entry in [
+            "nnoremap",
+            "qnoremap x accept",
+            "nnoremap x",
+            "nunmap x accept",
+            "nnoremap x no-such-action",
+            "nnoremap x accept extra",
+        ] {
+            assert!(keymap.apply(entry, &[]).is_err(), "{}", entry);
+        }
+    }
+}
@@ -12,3 +12,48 @@ pub mod tui;
 
 /// Event handler.
 pub mod handler;
+
+/// Command-line interface.
+pub mod cli;
+
+/// Background search worker.
+pub mod worker;
+
+/// Fuzzy matcher.
+pub mod fuzzy;
+
+/// Editor launch templates.
+pub mod editor;
+
+/// Neovim RPC client.
+pub mod nvim;
+
+/// User configuration.
+pub mod config;
+
+/// Key mappings.
+pub mod keymap;
+
+/// Vi operators and motions on the query.
+pub mod vi;
+
+/// Search history.
+pub mod history;
+
+/// Ex command line.
+pub mod ex;
+
+/// Search options.
+pub mod options;
+
+/// Line replacements written back to files.
+pub mod patch;
+
+/// Search and replace across files.
+pub mod substitute;
+
+/// Editable results.
+pub mod edit;
+
+/// Undo journal of file changes.
+pub mod journal;
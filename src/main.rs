@@ -1,29 +1,79 @@
-use lens::app::{App, AppResult};
+use lens::app::{App, AppResult, Message};
+use lens::cli::Cli;
+use lens::config::Config;
 use lens::event::{Event, EventHandler};
-use lens::handler::handle_key_events;
+use lens::handler::{get_preview, handle_key_events, handle_search_events, handle_tick, refresh};
+use lens::history::InputHistory;
+use lens::journal::Journal;
 use lens::tui::Tui;
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
-use std::io;
+use std::io::{self, Write};
+use std::process;
 
 fn main() -> AppResult<()> {
-    let mut app = App::new().unwrap_or_default();
+    let cli = Cli::parse_validated();
+    let (config, errors) = Config::load();
+    let events = EventHandler::new(config.tick_rate);
+    // A filter session is driven by its input, not by whatever was last searched.
+    let mut app = if cli.filter {
+        App::default()
+    } else {
+        App::new().unwrap_or_default()
+    };
+    app.worker.connect(events.sender());
+    app.config = config;
+    app.search_history = InputHistory::load("history", app.config.history_size);
+    app.command_history = InputHistory::load("command_history", app.config.history_size);
+    app.journal = Journal::load();
+    if !errors.is_empty() {
+        app.message = Some(Message::Error(errors.join("; ")));
+    }
+    let (query, filter) = (cli.query.is_some() || cli.files || cli.filter, cli.filter);
+    cli.apply(&mut app)?;
+    if query {
+        refresh(&mut app)?;
+    } else {
+        get_preview(&mut app)?;
+    }
+
     let backend = CrosstermBackend::new(io::stderr());
     let terminal = Terminal::new(backend)?;
-    let events = EventHandler::new(250);
     let mut tui = Tui::new(terminal, events);
     tui.init()?;
 
     while app.running {
         tui.draw(&mut app)?;
         match tui.events.next()? {
-            Event::Tick => app.tick(),
+            Event::Tick => handle_tick(&mut app, &mut tui)?,
             Event::Key(key_event) => handle_key_events(key_event, &mut app, &mut tui)?,
             Event::Mouse(_) => {}
             Event::Resize(_, _) => {}
+            Event::Search {
+                id,
+                results,
+                skipped,
+                done,
+            } => handle_search_events(id, results, skipped, done, &mut app)?,
         }
     }
 
+    app.worker.cancel();
     tui.exit()?;
+
+    if filter {
+        let code = match &app.output {
+            Some(lines) if !lines.is_empty() => 0,
+            Some(_) => 1,
+            None => 130,
+        };
+        let mut stdout = io::stdout().lock();
+        for line in app.output.iter().flatten() {
+            writeln!(stdout, "{}", line)?;
+        }
+        stdout.flush()?;
+        process::exit(code);
+    }
+
     Ok(())
 }
@@ -0,0 +1,296 @@
+use crate::app::Match;
+use rmpv::Value;
+use std::{
+    io::{BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Minimal msgpack-RPC client for a running Neovim, as started with `nvim --listen`.
+pub struct Nvim {
+    reader: BufReader<Box<dyn Stream>>,
+    id: u64,
+}
+
+impl Nvim {
+    /// Connects to a Unix socket path or a `host:port` TCP address.
+    pub fn connect(address: &str) -> anyhow::Result<Self> {
+        let stream: Box<dyn Stream> = if tcp_address(address) {
+            let stream = TcpStream::connect(address)?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            Box::new(stream)
+        } else {
+            unix_stream(address)?
+        };
+
+        Ok(Self {
+            reader: BufReader::new(stream),
+            id: 0,
+        })
+    }
+
+    fn call(&mut self, method: &str, params: Vec<Value>) -> anyhow::Result<Value> {
+        self.id += 1;
+        let request = Value::Array(vec![
+            0.into(),
+            self.id.into(),
+            method.into(),
+            Value::Array(params),
+        ]);
+        let stream = self.reader.get_mut();
+        rmpv::encode::write_value(stream, &request)?;
+        stream.flush()?;
+
+        // Skip notifications and anything else until our response comes back.
+        loop {
+            let message = rmpv::decode::read_value(&mut self.reader)?;
+            let Value::Array(message) = message else {
+                continue;
+            };
+            match message.as_slice() {
+                [kind, id, error, result]
+                    if kind.as_u64() == Some(1) && id.as_u64() == Some(self.id) =>
+                {
+                    if !error.is_nil() {
+                        anyhow::bail!("{} failed: {}", method, error_message(error));
+                    }
+                    return Ok(result.clone());
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    /// Splits the window when its buffer has unsaved changes, which Neovim won't leave
+    /// for another file (E37).
+    fn make_room(&mut self) -> anyhow::Result<()> {
+        let modified = self.call("nvim_eval", vec!["&modified".into()])?;
+        if modified.as_u64() == Some(1) {
+            self.call("nvim_command", vec!["split".into()])?;
+        }
+
+        Ok(())
+    }
+
+    /// Jumps to a single location, or loads several into the quickfix list and jumps to
+    /// the first one.
+    pub fn open(&mut self, locations: &[&Match]) -> anyhow::Result<()> {
+        match locations {
+            [] => Ok(()),
+            [location] => {
+                let path = location.path.to_string_lossy().to_string();
+                let escaped = self.call(
+                    "nvim_call_function",
+                    vec!["fnameescape".into(), Value::Array(vec![path.into()])],
+                )?;
+                let escaped = escaped.as_str().unwrap_or_default().to_string();
+                // Unlike `:edit`, `:drop` goes to a window already showing the file, and
+                // splits rather than fail when the current buffer has unsaved changes.
+                self.call("nvim_command", vec![format!("drop {}", escaped).into()])?;
+                self.call(
+                    "nvim_win_set_cursor",
+                    vec![
+                        0.into(),
+                        Value::Array(vec![
+                            Value::from(location.line),
+                            Value::from(location.column.saturating_sub(1)),
+                        ]),
+                    ],
+                )?;
+                Ok(())
+            }
+            locations => {
+                let items = locations
+                    .iter()
+                    .map(|location| {
+                        Value::Map(vec![
+                            (
+                                "filename".into(),
+                                location.path.to_string_lossy().as_ref().into(),
+                            ),
+                            ("lnum".into(), Value::from(location.line)),
+                            ("col".into(), Value::from(location.column)),
+                            ("text".into(), location.text.as_str().into()),
+                        ])
+                    })
+                    .collect::<Vec<_>>();
+                self.call(
+                    "nvim_call_function",
+                    vec!["setqflist".into(), Value::Array(vec![Value::Array(items)])],
+                )?;
+                self.make_room()?;
+                self.call("nvim_command", vec!["cfirst".into()])?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Neovim reports errors as `[type, message]`.
+fn error_message(error: &Value) -> String {
+    match error {
+        Value::Array(parts) => parts
+            .get(1)
+            .and_then(Value::as_str)
+            .unwrap_or("unknown error")
+            .to_string(),
+        error => error.to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn unix_stream(address: &str) -> anyhow::Result<Box<dyn Stream>> {
+    let stream = UnixStream::connect(address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+fn unix_stream(_address: &str) -> anyhow::Result<Box<dyn Stream>> {
+    anyhow::bail!("only host:port Neovim servers are supported on this platform")
+}
+
+fn tcp_address(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            !host.is_empty() && !host.contains('/') && port.parse::<u16>().is_ok()
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env, fs,
+        path::{Path, PathBuf},
+        process::{Child, Command, Stdio},
+        thread,
+    };
+
+    /// A headless `nvim --listen`, killed when dropped.
+    struct Server {
+        child: Child,
+        socket: PathBuf,
+    }
+
+    impl Server {
+        fn start(name: &str) -> Self {
+            let socket = env::temp_dir().join(format!("lens-{}-{}.sock", name, std::process::id()));
+            let _ = fs::remove_file(&socket);
+            let child = Command::new("nvim")
+                .args(["--headless", "--clean", "--listen"])
+                .arg(&socket)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("nvim is on the PATH");
+            for _ in 0..100 {
+                if socket.exists() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+
+            Self { child, socket }
+        }
+
+        fn connect(&self) -> Nvim {
+            Nvim::connect(&self.socket.to_string_lossy()).expect("connects")
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+            let _ = fs::remove_file(&self.socket);
+        }
+    }
+
+    fn location(path: &Path, line: usize, column: usize) -> Match {
+        Match {
+            path: path.to_path_buf(),
+            line,
+            column,
+            text: String::new(),
+            submatches: Vec::new(),
+            score: 0,
+            marked: false,
+            context: false,
+        }
+    }
+
+    fn file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("lens-{}-{}.txt", name, std::process::id()));
+        fs::write(&path, "one\ntwo\nthree\n").expect("writes");
+        path
+    }
+
+    /// The file and 1-based cursor of the current window.
+    fn position(nvim: &mut Nvim) -> (String, u64, u64) {
+        let name = nvim
+            .call("nvim_eval", vec!["expand('%:p')".into()])
+            .unwrap();
+        let cursor = nvim.call("nvim_win_get_cursor", vec![0.into()]).unwrap();
+        let cursor = cursor.as_array().unwrap();
+        (
+            name.as_str().unwrap().to_string(),
+            cursor[0].as_u64().unwrap(),
+            cursor[1].as_u64().unwrap() + 1,
+        )
+    }
+
+    #[test]
+    #[ignore = "needs nvim"]
+    fn jumps_past_unsaved_changes() {
+        let server = Server::start("jump");
+        let mut nvim = server.connect();
+        let path = file("jump");
+        // Leave a modified buffer behind, which `:edit` would refuse to abandon.
+        nvim.call("nvim_command", vec!["call setline(1, 'unsaved')".into()])
+            .unwrap();
+
+        nvim.open(&[&location(&path, 2, 3)]).unwrap();
+        let canonical = fs::canonicalize(&path).unwrap();
+        assert_eq!(
+            position(&mut nvim),
+            (canonical.to_string_lossy().to_string(), 2, 3)
+        );
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    #[ignore = "needs nvim"]
+    fn loads_several_into_the_quickfix_list() {
+        let server = Server::start("quickfix");
+        let mut nvim = server.connect();
+        let (first, second) = (file("first"), file("second"));
+        nvim.call("nvim_command", vec!["call setline(1, 'unsaved')".into()])
+            .unwrap();
+
+        nvim.open(&[&location(&first, 3, 1), &location(&second, 1, 2)])
+            .unwrap();
+        let canonical = fs::canonicalize(&first).unwrap();
+        assert_eq!(
+            position(&mut nvim),
+            (canonical.to_string_lossy().to_string(), 3, 1)
+        );
+        let count = nvim
+            .call("nvim_eval", vec!["len(getqflist())".into()])
+            .unwrap();
+        assert_eq!(count.as_u64(), Some(2));
+        let _ = fs::remove_file(first);
+        let _ = fs::remove_file(second);
+    }
+}
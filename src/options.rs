@@ -0,0 +1,411 @@
+use crate::{
+    app::{Matcher, Source},
+    config::Config,
+};
+use serde::{Deserialize, Serialize};
+
+/// Search settings changed at runtime with `:set`, and read from the `[search]` table of
+/// the config. They only ever add flags after the configured and command-line `rg`
+/// arguments. The config spells them with underscores like the rest of it, `:set` with
+/// dashes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchOptions {
+    pub ignorecase: bool,
+    /// With `ignorecase`, match case-sensitively once the query has an uppercase letter.
+    pub smartcase: bool,
+    /// Search hidden files and directories.
+    pub hidden: bool,
+    /// Respect `.gitignore` and friends.
+    pub ignore: bool,
+    /// Follow symbolic links.
+    pub follow: bool,
+    /// Treat the query as a literal string rather than a regex.
+    pub fixed_strings: bool,
+    /// Only match whole words.
+    pub word: bool,
+    pub multiline: bool,
+    /// Lines of context around every match, listed along with the results.
+    pub context: usize,
+    /// Only search files matching this glob.
+    pub glob: String,
+    /// Only search files of this `rg --type`.
+    #[serde(rename = "type")]
+    pub file_type: String,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            ignorecase: true,
+            smartcase: true,
+            hidden: false,
+            ignore: true,
+            follow: false,
+            fixed_strings: false,
+            word: false,
+            multiline: false,
+            context: 0,
+            glob: String::new(),
+            file_type: String::new(),
+        }
+    }
+}
+
+/// A setting's value as `:set` and the options panel see it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Number(u64),
+    Text(String),
+}
+
+/// Every setting `:set` knows, grouped the way the options panel lists them.
+pub const GROUPS: [(&str, &[&str]); 4] = [
+    (
+        "Search",
+        &[
+            "ignorecase",
+            "smartcase",
+            "hidden",
+            "ignore",
+            "follow",
+            "fixed-strings",
+            "word",
+            "multiline",
+            "context",
+        ],
+    ),
+    ("Filters", &["glob", "type"]),
+    ("Layout", &["results-width", "preview-width"]),
+    (
+        "Editor",
+        &["preview-context", "timeout", "persist-registers"],
+    ),
+];
+
+/// Every setting name, in the order `:set` lists them.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    GROUPS.iter().flat_map(|(_, names)| names.iter().copied())
+}
+
+impl SearchOptions {
+    pub fn get(&self, name: &str) -> Option<Value> {
+        Some(match name {
+            "ignorecase" => Value::Bool(self.ignorecase),
+            "smartcase" => Value::Bool(self.smartcase),
+            "hidden" => Value::Bool(self.hidden),
+            "ignore" => Value::Bool(self.ignore),
+            "follow" => Value::Bool(self.follow),
+            "fixed-strings" => Value::Bool(self.fixed_strings),
+            "word" => Value::Bool(self.word),
+            "multiline" => Value::Bool(self.multiline),
+            "context" => Value::Number(self.context as u64),
+            "glob" => Value::Text(self.glob.clone()),
+            "type" => Value::Text(self.file_type.clone()),
+            _ => return None,
+        })
+    }
+
+    /// Sets `name`, or returns `None` if there's no such option or `value` doesn't fit
+    /// it.
+    pub fn set(&mut self, name: &str, value: Value) -> Option<()> {
+        match (name, value) {
+            ("ignorecase", Value::Bool(value)) => self.ignorecase = value,
+            ("smartcase", Value::Bool(value)) => self.smartcase = value,
+            ("hidden", Value::Bool(value)) => self.hidden = value,
+            ("ignore", Value::Bool(value)) => self.ignore = value,
+            ("follow", Value::Bool(value)) => self.follow = value,
+            ("fixed-strings", Value::Bool(value)) => self.fixed_strings = value,
+            ("word", Value::Bool(value)) => self.word = value,
+            ("multiline", Value::Bool(value)) => self.multiline = value,
+            ("context", Value::Number(value)) => self.context = value.try_into().ok()?,
+            ("glob", Value::Text(value)) => self.glob = value,
+            ("type", Value::Text(value)) => self.file_type = value,
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    /// The `rg` flags these options stand for when searching `source` with `matcher`.
+    pub fn flags(&self, source: Source, matcher: Matcher) -> Vec<String> {
+        let mut flags = Vec::new();
+        let mut flag = |on: bool, name: &str| {
+            if on {
+                flags.push(name.to_string());
+            }
+        };
+        flag(self.hidden, "--hidden");
+        flag(!self.ignore, "--no-ignore");
+        flag(self.follow, "--follow");
+        if source == Source::Content {
+            let case = match (self.ignorecase, self.smartcase) {
+                (true, true) => "--smart-case",
+                (true, false) => "--ignore-case",
+                (false, _) => "--case-sensitive",
+            };
+            flag(true, case);
+            // A fuzzy query is turned into a regex, so it can't be taken literally.
+            flag(
+                self.fixed_strings && matcher == Matcher::Regex,
+                "--fixed-strings",
+            );
+            flag(self.word, "--word-regexp");
+            flag(self.multiline, "--multiline");
+        }
+
+        if source == Source::Content && self.context > 0 {
+            flags.extend(["--context".to_string(), self.context.to_string()]);
+        }
+        if !self.glob.is_empty() {
+            flags.extend(["--glob".to_string(), self.glob.clone()]);
+        }
+        if !self.file_type.is_empty() {
+            flags.extend(["--type".to_string(), self.file_type.clone()]);
+        }
+
+        flags
+    }
+}
+
+/// `name` the way `:set` shows it, like `hidden`, `noignore` or `context=3`.
+pub fn show(config: &Config, name: &str) -> Option<String> {
+    Some(match config.get(name)? {
+        Value::Bool(true) => name.to_string(),
+        Value::Bool(false) => format!("no{}", name),
+        Value::Number(value) => format!("{}={}", name, value),
+        Value::Text(value) => format!("{}={}", name, value),
+    })
+}
+
+/// Every setting, as `:set` with no arguments lists them.
+pub fn list(config: &Config) -> String {
+    names()
+        .filter_map(|name| show(config, name))
+        .collect::<Vec<_>>()
+        .join("  ")
+}
+
+/// Applies one `:set` argument: `name`, `noname`, `invname`, `name!`, `name=value` or
+/// `name?`. Returns what to show when the argument asks for a value rather than
+/// changing one. Changes that leave the config invalid are undone.
+pub fn apply(config: &mut Config, argument: &str) -> Result<Option<String>, String> {
+    let unknown = || format!("unknown option: {}", argument);
+    let invalid = || format!("invalid argument: {}", argument);
+    if let Some(name) = argument.strip_suffix('?') {
+        return show(config, name).map(Some).ok_or_else(unknown);
+    }
+
+    let (name, value) = match argument.split_once('=') {
+        Some((name, value)) => match config.get(name).ok_or_else(unknown)? {
+            Value::Bool(_) => return Err(invalid()),
+            Value::Number(_) => (name, Value::Number(value.parse().map_err(|_| invalid())?)),
+            Value::Text(_) => (name, Value::Text(value.to_string())),
+        },
+        None => match config.get(argument) {
+            Some(Value::Bool(_)) => (argument, Value::Bool(true)),
+            // Like vim, naming a number or string option shows it.
+            Some(_) => return Ok(show(config, argument)),
+            None => {
+                let (name, change): (&str, fn(bool) -> bool) =
+                    if let Some(name) = argument.strip_suffix('!') {
+                        (name, |value| !value)
+                    } else if let Some(name) = argument.strip_prefix("inv") {
+                        (name, |value| !value)
+                    } else if let Some(name) = argument.strip_prefix("no") {
+                        (name, |_| false)
+                    } else {
+                        return Err(unknown());
+                    };
+                match config.get(name).ok_or_else(unknown)? {
+                    Value::Bool(value) => (name, Value::Bool(change(value))),
+                    _ => return Err(invalid()),
+                }
+            }
+        },
+    };
+
+    let before = config.clone();
+    config.set(name, value).ok_or_else(invalid)?;
+    if let Some(error) = config.validate().into_iter().next() {
+        *config = before;
+        return Err(error);
+    }
+    if let Some(name) = names().find(|&known| known == name) {
+        if !config.changed.contains(&name) {
+            config.changed.push(name);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Setting names starting with `prefix`, for Tab completion. Toggles also complete with
+/// `no` and `inv` in front once those are typed.
+pub fn complete(prefix: &str) -> Vec<String> {
+    let config = Config::default();
+    let mut completions = Vec::new();
+    for name in names() {
+        completions.push(name.to_string());
+        // `no...` and `inv...` only show up when asked for, not for every `:set <Tab>`.
+        if let Some(Value::Bool(_)) = config.get(name) {
+            completions.push(format!("no{}", name));
+            completions.push(format!("inv{}", name));
+        }
+    }
+
+    let negated = prefix.starts_with("no") || prefix.starts_with("inv");
+    completions
+        .retain(|name| name.starts_with(prefix) && (negated || names().any(|known| known == name)));
+
+    completions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_every_spelling_of_a_toggle() {
+        let mut config = Config::default();
+        assert_eq!(apply(&mut config, "hidden"), Ok(None));
+        assert!(config.search.hidden);
+        assert_eq!(apply(&mut config, "nohidden"), Ok(None));
+        assert!(!config.search.hidden);
+        assert_eq!(apply(&mut config, "invhidden"), Ok(None));
+        assert!(config.search.hidden);
+        assert_eq!(apply(&mut config, "hidden!"), Ok(None));
+        assert!(!config.search.hidden);
+        assert_eq!(
+            apply(&mut config, "hidden?"),
+            Ok(Some("nohidden".to_string()))
+        );
+        assert_eq!(
+            apply(&mut config, "ignore?"),
+            Ok(Some("ignore".to_string()))
+        );
+    }
+
+    #[test]
+    fn applies_values() {
+        let mut config = Config::default();
+        assert_eq!(apply(&mut config, "context=3"), Ok(None));
+        assert_eq!(config.search.context, 3);
+        assert_eq!(apply(&mut config, "glob=*.rs"), Ok(None));
+        assert_eq!(config.search.glob, "*.rs");
+        assert_eq!(
+            apply(&mut config, "context"),
+            Ok(Some("context=3".to_string()))
+        );
+
+        let error = |argument: &str| Err(format!("invalid argument: {}", argument));
+        assert_eq!(apply(&mut config, "context=x"), error("context=x"));
+        assert_eq!(apply(&mut config, "hidden=1"), error("hidden=1"));
+        assert_eq!(apply(&mut config, "nocontext"), error("nocontext"));
+        assert_eq!(
+            apply(&mut config, "nosuch"),
+            Err("unknown option: nosuch".to_string())
+        );
+        assert_eq!(
+            apply(&mut config, "nosuch?"),
+            Err("unknown option: nosuch?".to_string())
+        );
+    }
+
+    #[test]
+    fn rolls_back_what_leaves_the_config_invalid() {
+        let mut config = Config::default();
+        assert!(apply(&mut config, "results-width=70").is_err());
+        assert_eq!(config.layout.results, Config::default().layout.results);
+        assert!(apply(&mut config, "timeout=0").is_err());
+        assert_eq!(config.timeout, Config::default().timeout);
+        assert!(config.changed.is_empty());
+    }
+
+    #[test]
+    fn tracks_what_changed_once() {
+        let mut config = Config::default();
+        apply(&mut config, "hidden").unwrap();
+        apply(&mut config, "context=2").unwrap();
+        apply(&mut config, "nohidden").unwrap();
+        apply(&mut config, "glob?").unwrap();
+        assert_eq!(config.changed, ["hidden", "context"]);
+    }
+
+    #[test]
+    fn completes_negations_only_when_asked() {
+        assert_eq!(complete("hi"), ["hidden"]);
+        assert_eq!(complete("ignore"), ["ignorecase", "ignore"]);
+        assert_eq!(complete("nohi"), ["nohidden"]);
+        assert_eq!(complete("invf"), ["invfollow", "invfixed-strings"]);
+        assert!(complete("noc").is_empty());
+    }
+
+    #[test]
+    fn lists_every_setting() {
+        let config = Config::default();
+        let list = list(&config);
+        assert!(list.starts_with("ignorecase  smartcase  nohidden  ignore  nofollow"));
+        assert!(list.contains("context=0  glob=  type="));
+    }
+
+    #[test]
+    fn only_content_searches_get_content_flags() {
+        let options = SearchOptions {
+            hidden: true,
+            ignore: false,
+            fixed_strings: true,
+            word: true,
+            context: 2,
+            glob: "*.rs".to_string(),
+            ..SearchOptions::default()
+        };
+        assert_eq!(
+            options.flags(Source::Content, Matcher::Regex),
+            [
+                "--hidden",
+                "--no-ignore",
+                "--smart-case",
+                "--fixed-strings",
+                "--word-regexp",
+                "--context",
+                "2",
+                "--glob",
+                "*.rs"
+            ]
+        );
+        assert_eq!(
+            options.flags(Source::Content, Matcher::Fuzzy),
+            [
+                "--hidden",
+                "--no-ignore",
+                "--smart-case",
+                "--word-regexp",
+                "--context",
+                "2",
+                "--glob",
+                "*.rs"
+            ]
+        );
+        assert_eq!(
+            options.flags(Source::Files, Matcher::Regex),
+            ["--hidden", "--no-ignore", "--glob", "*.rs"]
+        );
+    }
+
+    #[test]
+    fn picks_the_case_flag() {
+        let flags = |ignorecase, smartcase| {
+            let options = SearchOptions {
+                ignorecase,
+                smartcase,
+                ..SearchOptions::default()
+            };
+            options.flags(Source::Content, Matcher::Regex)
+        };
+        assert_eq!(flags(true, true), ["--smart-case"]);
+        assert_eq!(flags(true, false), ["--ignore-case"]);
+        assert_eq!(flags(false, true), ["--case-sensitive"]);
+    }
+}
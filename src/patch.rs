@@ -0,0 +1,388 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// A replacement for one line of a file. `line` is 1-based, and neither text has its
+/// line ending.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hunk {
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+    pub accepted: bool,
+}
+
+/// Line replacements for one file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilePatch {
+    pub path: PathBuf,
+    pub hunks: Vec<Hunk>,
+    /// When set, the file must not have been modified since.
+    #[serde(skip)]
+    pub unchanged_since: Option<SystemTime>,
+}
+
+impl FilePatch {
+    /// `contents` with the accepted hunks applied, keeping each line's ending. Fails if
+    /// a line to replace no longer reads what it did.
+    fn apply(&self, contents: &str) -> Result<String, String> {
+        let mut lines = contents.split_inclusive('\n').collect::<Vec<_>>();
+        let mut replaced = Vec::new();
+        for hunk in self.hunks.iter().filter(|hunk| hunk.accepted) {
+            let conflict = || {
+                format!(
+                    "{}:{} has changed on disk, nothing written",
+                    self.path.display(),
+                    hunk.line
+                )
+            };
+            let current = lines.get(hunk.line.wrapping_sub(1)).ok_or_else(conflict)?;
+            let text = current.trim_end_matches(['\n', '\r']);
+            if text != hunk.before {
+                return Err(conflict());
+            }
+            replaced.push((
+                hunk.line - 1,
+                format!("{}{}", hunk.after, &current[text.len()..]),
+            ));
+        }
+
+        for (index, line) in &replaced {
+            lines[*index] = line;
+        }
+        Ok(lines.concat())
+    }
+}
+
+/// What writing a set of patches changed.
+#[derive(Debug, Default)]
+pub struct Written {
+    pub lines: usize,
+    pub files: usize,
+    /// The files replaced, in the order of the patches.
+    pub paths: Vec<PathBuf>,
+}
+
+/// Why writing a set of patches stopped, along with whatever was already replaced by
+/// then.
+#[derive(Debug)]
+pub struct Failed {
+    pub error: String,
+    pub written: Written,
+}
+
+impl From<String> for Failed {
+    fn from(error: String) -> Self {
+        Self {
+            error,
+            written: Written::default(),
+        }
+    }
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.written.files {
+            0 => write!(f, "{}", self.error),
+            files => write!(
+                f,
+                "{}, after writing {}",
+                self.error,
+                lines_in_files(self.written.lines, files)
+            ),
+        }
+    }
+}
+
+/// How many lines in how many files, like `1 line in 2 files`.
+pub fn lines_in_files(lines: usize, files: usize) -> String {
+    let plural = |count: usize, noun: &str| {
+        if count == 1 {
+            format!("{} {}", count, noun)
+        } else {
+            format!("{} {}s", count, noun)
+        }
+    };
+
+    format!("{} in {}", plural(lines, "line"), plural(files, "file"))
+}
+
+/// Writes the accepted hunks of every patch. Nothing is written unless every file still
+/// has the lines the hunks expect and every new version could be put next to its file,
+/// and each file is then replaced in one step so it's never seen half-written, short of
+/// hard-linked ones, which are written over in place.
+pub fn write(patches: &[FilePatch]) -> Result<Written, Failed> {
+    let updated = prepare(patches)?;
+
+    let mut staged = Vec::new();
+    for (patch, contents) in updated {
+        match stage(&patch.path, &contents) {
+            Ok(file) => staged.push((patch, file)),
+            Err(e) => {
+                staged.iter().for_each(|(_, file)| file.discard());
+                return Err(format!("{}: {}", patch.path.display(), e).into());
+            }
+        }
+    }
+
+    let mut written = Written::default();
+    for (index, (patch, file)) in staged.iter().enumerate() {
+        if let Err(e) = file.replace() {
+            staged[index..].iter().for_each(|(_, file)| file.discard());
+            return Err(Failed {
+                error: format!("{}: {}", patch.path.display(), e),
+                written,
+            });
+        }
+        written.lines += patch.hunks.iter().filter(|hunk| hunk.accepted).count();
+        written.files += 1;
+        written.paths.push(patch.path.clone());
+    }
+
+    Ok(written)
+}
+
+/// The new contents of every file with accepted hunks, or why they can't be written.
+fn prepare(patches: &[FilePatch]) -> Result<Vec<(&FilePatch, String)>, String> {
+    let mut updated = Vec::new();
+    for patch in patches {
+        if !patch.hunks.iter().any(|hunk| hunk.accepted) {
+            continue;
+        }
+        let error = |e: io::Error| format!("{}: {}", patch.path.display(), e);
+        if let Some(since) = patch.unchanged_since {
+            if fs::metadata(&patch.path)
+                .and_then(|m| m.modified())
+                .map_err(error)?
+                > since
+            {
+                return Err(format!(
+                    "{} changed on disk since the search, nothing written",
+                    patch.path.display()
+                ));
+            }
+        }
+        let contents = fs::read_to_string(&patch.path).map_err(error)?;
+        updated.push((patch, patch.apply(&contents)?));
+    }
+
+    Ok(updated)
+}
+
+/// A new version of a file, written out next to it.
+struct Staged {
+    temporary: PathBuf,
+    /// The file itself, with any symbolic links resolved.
+    target: PathBuf,
+    /// Whether the file has other hard links, which renaming over it would cut off.
+    linked: bool,
+}
+
+impl Staged {
+    /// Puts the new version in place: renamed over the file, or copied into it when it's
+    /// hard linked.
+    fn replace(&self) -> io::Result<()> {
+        if !self.linked {
+            return fs::rename(&self.temporary, &self.target);
+        }
+        fs::copy(&self.temporary, &self.target)?;
+        self.discard();
+        Ok(())
+    }
+
+    fn discard(&self) {
+        let _ = fs::remove_file(&self.temporary);
+    }
+}
+
+/// Writes `contents` to a temporary file next to the file `path` leads to, with the same
+/// permissions.
+fn stage(path: &Path, contents: &str) -> io::Result<Staged> {
+    let target = fs::canonicalize(path)?;
+    let metadata = fs::metadata(&target)?;
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let staged = Staged {
+        temporary: target.with_file_name(format!(".{}.lens-{}", name, std::process::id())),
+        #[cfg(unix)]
+        linked: std::os::unix::fs::MetadataExt::nlink(&metadata) > 1,
+        #[cfg(not(unix))]
+        linked: false,
+        target,
+    };
+    let result = (|| {
+        let mut file = fs::File::create(&staged.temporary)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::set_permissions(&staged.temporary, metadata.permissions())
+    })();
+    if let Err(e) = result {
+        staged.discard();
+        return Err(e);
+    }
+
+    Ok(staged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn patch(path: &str, hunks: &[(usize, &str, &str)]) -> FilePatch {
+        FilePatch {
+            path: path.into(),
+            hunks: hunks
+                .iter()
+                .map(|&(line, before, after)| Hunk {
+                    line,
+                    before: before.to_string(),
+                    after: after.to_string(),
+                    accepted: true,
+                })
+                .collect(),
+            unchanged_since: None,
+        }
+    }
+
+    /// A fresh directory for one test's files.
+    fn directory(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("lens-patch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn applies_hunks_keeping_line_endings() {
+        let patch = patch("a", &[(1, "one", "1"), (3, "three", "3")]);
+        assert_eq!(patch.apply("one\r\ntwo\nthree").unwrap(), "1\r\ntwo\n3");
+    }
+
+    #[test]
+    fn skips_rejected_hunks() {
+        let mut patch = patch("a", &[(1, "one", "1"), (2, "two", "2")]);
+        patch.hunks[1].accepted = false;
+        assert_eq!(patch.apply("one\ntwo\n").unwrap(), "1\ntwo\n");
+    }
+
+    #[test]
+    fn refuses_lines_that_changed() {
+        let conflict = "a:2 has changed on disk, nothing written";
+        assert_eq!(
+            patch("a", &[(2, "two", "2")])
+                .apply("one\nTWO\n")
+                .unwrap_err(),
+            conflict
+        );
+        assert_eq!(
+            patch("a", &[(2, "two", "2")]).apply("one\n").unwrap_err(),
+            conflict
+        );
+        assert!(patch("a", &[(0, "", "x")]).apply("one\n").is_err());
+    }
+
+    #[test]
+    fn writes_every_file_or_none() {
+        let dir = directory("write");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "foo\nbar\n").unwrap();
+        fs::write(&b, "foo\n").unwrap();
+        let (a_path, b_path) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        let failed = write(&[
+            patch(a_path, &[(1, "foo", "baz")]),
+            patch(b_path, &[(1, "nope", "baz")]),
+        ])
+        .unwrap_err();
+        assert_eq!(failed.written.files, 0);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "foo\nbar\n");
+
+        let written = write(&[
+            patch(a_path, &[(1, "foo", "baz"), (2, "bar", "qux")]),
+            patch(b_path, &[(1, "foo", "baz")]),
+        ])
+        .unwrap();
+        assert_eq!((written.lines, written.files), (3, 2));
+        assert_eq!(written.paths, [a.clone(), b.clone()]);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "baz\nqux\n");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "baz\n");
+        // No temporary files are left behind.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stages_every_file_before_replacing_any() {
+        let dir = directory("stage");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "foo\n").unwrap();
+        fs::write(&b, "foo\n").unwrap();
+        // Something in the way of the temporary file for `b`.
+        fs::create_dir(dir.join(format!(".b.lens-{}", std::process::id()))).unwrap();
+
+        let failed = write(&[
+            patch(a.to_str().unwrap(), &[(1, "foo", "bar")]),
+            patch(b.to_str().unwrap(), &[(1, "foo", "bar")]),
+        ])
+        .unwrap_err();
+        assert_eq!(failed.written.files, 0);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "foo\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_symbolic_and_hard_links() {
+        let dir = directory("links");
+        let (file, symlink, hardlink) =
+            (dir.join("file"), dir.join("symlink"), dir.join("hardlink"));
+        fs::write(&file, "foo\nbar\n").unwrap();
+        std::os::unix::fs::symlink(&file, &symlink).unwrap();
+        fs::hard_link(&file, &hardlink).unwrap();
+
+        write(&[patch(symlink.to_str().unwrap(), &[(1, "foo", "baz")])]).unwrap();
+        assert!(fs::symlink_metadata(&symlink)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        write(&[patch(hardlink.to_str().unwrap(), &[(2, "bar", "qux")])]).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "baz\nqux\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn counts_lines_and_files() {
+        assert_eq!(lines_in_files(1, 1), "1 line in 1 file");
+        assert_eq!(lines_in_files(3, 2), "3 lines in 2 files");
+        assert_eq!(lines_in_files(0, 0), "0 lines in 0 files");
+    }
+
+    #[test]
+    fn tells_what_was_written_before_failing() {
+        let failed = Failed {
+            error: "b: denied".to_string(),
+            written: Written {
+                lines: 3,
+                files: 1,
+                paths: vec!["a".into()],
+            },
+        };
+        assert_eq!(
+            failed.to_string(),
+            "b: denied, after writing 3 lines in 1 file"
+        );
+        assert_eq!(
+            Failed::from("b: denied".to_string()).to_string(),
+            "b: denied"
+        );
+    }
+}
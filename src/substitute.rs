@@ -0,0 +1,307 @@
+use crate::{
+    app::Match,
+    patch::{FilePatch, Hunk},
+};
+use regex::{Regex, RegexBuilder};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::PathBuf,
+};
+
+/// A parsed `:s/pattern/replacement/flags`.
+#[derive(Debug)]
+pub struct Substitute {
+    regex: Regex,
+    /// The replacement in `regex` syntax, `${1}` for a group.
+    replacement: String,
+    /// The `g` flag, replacing every match on a line rather than the first.
+    global: bool,
+}
+
+/// Splits `text` on unescaped `delimiter`, unescaping it where it's escaped.
+fn split(text: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().expect("starts with a part");
+        match c {
+            '\\' if chars.peek() == Some(&delimiter) => part.push(chars.next().unwrap_or(c)),
+            '\\' => {
+                part.push(c);
+                part.extend(chars.next());
+            }
+            c if c == delimiter => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+
+    parts
+}
+
+/// Turns a vim replacement, where `&` and `\0` are the whole match and `\1` to `\9` are
+/// groups, into `regex` syntax.
+fn replacement(text: &str) -> String {
+    let mut replacement = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            ('&', _) => replacement.push_str("${0}"),
+            ('$', _) => replacement.push_str("$$"),
+            (_, true) => match chars.next() {
+                Some(digit @ '0'..='9') => replacement.push_str(&format!("${{{}}}", digit)),
+                Some('$') => replacement.push_str("$$"),
+                Some(c) => replacement.push(c),
+                None => replacement.push('\\'),
+            },
+            (c, false) => replacement.push(c),
+        }
+    }
+
+    replacement
+}
+
+impl Substitute {
+    /// Parses what follows `:s`. An empty pattern stands for `query`, and case follows
+    /// the `ignorecase` and `smartcase` options unless the `i` or `I` flag says
+    /// otherwise.
+    pub fn parse(
+        text: &str,
+        query: &str,
+        ignorecase: bool,
+        smartcase: bool,
+    ) -> Result<Self, String> {
+        let mut chars = text.chars();
+        let delimiter = chars
+            .next()
+            .filter(|c| {
+                !c.is_alphanumeric() && !c.is_whitespace() && !matches!(c, '\\' | '"' | '|')
+            })
+            .ok_or_else(|| "usage: s/pattern/replacement/[giI]".to_string())?;
+
+        let parts = split(chars.as_str(), delimiter);
+        let pattern = match parts[0].as_str() {
+            "" => query,
+            pattern => pattern,
+        };
+        if pattern.is_empty() {
+            return Err("no pattern to substitute".to_string());
+        }
+        let flags = parts.get(2).map_or("", String::as_str);
+        if parts.len() > 3 || flags.chars().any(|flag| !matches!(flag, 'g' | 'i' | 'I')) {
+            let trailing = parts[2..].join(&delimiter.to_string());
+            return Err(format!("trailing characters: {}", trailing));
+        }
+
+        let smart = smartcase && pattern.chars().any(char::is_uppercase);
+        let case_insensitive = match (flags.contains('i'), flags.contains('I')) {
+            (true, _) => true,
+            (_, true) => false,
+            _ => ignorecase && !smart,
+        };
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            regex,
+            replacement: replacement(parts.get(1).map_or("", String::as_str)),
+            global: flags.contains('g'),
+        })
+    }
+
+    /// `line` with the substitution made, or `None` when the pattern isn't on it.
+    fn replace(&self, line: &str) -> Option<String> {
+        if !self.regex.is_match(line) {
+            return None;
+        }
+        let limit = if self.global { 0 } else { 1 };
+
+        Some(
+            self.regex
+                .replacen(line, limit, self.replacement.as_str())
+                .into_owned(),
+        )
+    }
+
+    /// Works out the substitution on the lines of `results`, one patch per file with a
+    /// hunk per changed line. Files that can't be read are skipped.
+    pub fn patches(&self, results: &[&Match]) -> Vec<FilePatch> {
+        let mut lines = BTreeMap::<&PathBuf, BTreeSet<usize>>::new();
+        for result in results.iter().filter(|result| !result.context) {
+            lines.entry(&result.path).or_default().insert(result.line);
+        }
+
+        lines
+            .into_iter()
+            .filter_map(|(path, numbers)| {
+                let contents = fs::read_to_string(path).ok()?;
+                let file = contents.lines().collect::<Vec<_>>();
+                let hunks = numbers
+                    .into_iter()
+                    .filter_map(|line| {
+                        let before = file.get(line.checked_sub(1)?)?;
+                        let after = self.replace(before).filter(|after| after != before)?;
+                        Some(Hunk {
+                            line,
+                            before: before.to_string(),
+                            after,
+                            accepted: true,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                if hunks.is_empty() {
+                    None
+                } else {
+                    Some(FilePatch {
+                        path: path.clone(),
+                        hunks,
+                        unchanged_since: None,
+                    })
+                }
+            })
+            .collect()
+    }
+}
+
+/// Substitutions waiting to be looked over in the preview pane before they're written.
+#[derive(Debug, Default)]
+pub struct Review {
+    /// The `:s` command, for the journal.
+    pub command: String,
+    pub patches: Vec<FilePatch>,
+    /// Index of the highlighted hunk, counting through every file.
+    pub current: usize,
+}
+
+impl Review {
+    fn len(&self) -> usize {
+        self.patches.iter().map(|patch| patch.hunks.len()).sum()
+    }
+
+    /// The file and hunk the highlighted one is, by index.
+    pub fn position(&self) -> (usize, usize) {
+        let mut current = self.current;
+        for (file, patch) in self.patches.iter().enumerate() {
+            if current < patch.hunks.len() {
+                return (file, current);
+            }
+            current -= patch.hunks.len();
+        }
+
+        (0, 0)
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.len().max(1);
+    }
+
+    pub fn previous(&mut self) {
+        self.current = self
+            .current
+            .checked_sub(1)
+            .unwrap_or(self.len().saturating_sub(1));
+    }
+
+    pub fn first(&mut self) {
+        self.current = 0;
+    }
+
+    pub fn last(&mut self) {
+        self.current = self.len().saturating_sub(1);
+    }
+
+    fn hunks(&mut self) -> impl Iterator<Item = &mut Hunk> {
+        self.patches
+            .iter_mut()
+            .flat_map(|patch| patch.hunks.iter_mut())
+    }
+
+    /// Accepts or rejects the highlighted hunk and moves on to the next.
+    pub fn toggle(&mut self) {
+        let current = self.current;
+        if let Some(hunk) = self.hunks().nth(current) {
+            hunk.accepted = !hunk.accepted;
+        }
+        self.current = (self.current + 1).min(self.len().saturating_sub(1));
+    }
+
+    pub fn accept_all(&mut self) {
+        self.hunks().for_each(|hunk| hunk.accepted = true);
+    }
+
+    pub fn invert(&mut self) {
+        self.hunks().for_each(|hunk| hunk.accepted = !hunk.accepted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn substitute(text: &str) -> Substitute {
+        Substitute::parse(text, "query", true, true).expect("parses")
+    }
+
+    #[test]
+    fn splits_on_unescaped_delimiters() {
+        assert_eq!(split(r"a\/b/c/g", '/'), [r"a/b", "c", "g"]);
+        assert_eq!(split(r"a\.b#c", '#'), [r"a\.b", "c"]);
+        assert_eq!(split("", '/'), [""]);
+    }
+
+    #[test]
+    fn translates_vim_replacements() {
+        assert_eq!(replacement("<&>"), "<${0}>");
+        assert_eq!(replacement(r"\1-\0"), "${1}-${0}");
+        assert_eq!(replacement(r"$1 \$ \& \\"), r"$$1 $$ & \");
+        assert_eq!(replacement("trailing\\"), "trailing\\");
+    }
+
+    #[test]
+    fn substitutes_with_groups_and_flags() {
+        assert_eq!(
+            substitute(r"/(\w+)=(\w+)/\2=\1/")
+                .replace("a=b c=d")
+                .as_deref(),
+            Some("b=a c=d")
+        );
+        assert_eq!(substitute("/o/0/g").replace("foo").as_deref(), Some("f00"));
+        assert_eq!(
+            substitute("#a#[&]#").replace("cat").as_deref(),
+            Some("c[a]t")
+        );
+        assert_eq!(substitute("/x/y/").replace("abc"), None);
+        // An empty pattern falls back to the query.
+        assert_eq!(
+            substitute("//Q/").replace("a query").as_deref(),
+            Some("a Q")
+        );
+    }
+
+    #[test]
+    fn follows_the_case_options_and_flags() {
+        assert!(substitute("/foo/x/").replace("FOO").is_some());
+        assert!(substitute("/Foo/x/").replace("foo").is_none());
+        assert!(substitute("/Foo/x/i").replace("foo").is_some());
+        assert!(substitute("/foo/x/I").replace("FOO").is_none());
+        let exact = Substitute::parse("/foo/x/", "", false, true).unwrap();
+        assert!(exact.replace("FOO").is_none());
+    }
+
+    #[test]
+    fn rejects_bad_substitutions() {
+        let error =
+            |text: &str, query: &str| Substitute::parse(text, query, true, true).unwrap_err();
+        assert_eq!(error("", "q"), "usage: s/pattern/replacement/[giI]");
+        assert_eq!(
+            error("afooabara", "q"),
+            "usage: s/pattern/replacement/[giI]"
+        );
+        assert_eq!(error("//x/", ""), "no pattern to substitute");
+        assert_eq!(error("/a/b/gx", "q"), "trailing characters: gx");
+        assert_eq!(error("/a/b/g/c", "q"), "trailing characters: g/c");
+        assert!(error("/(/x/", "q").contains("unclosed group"));
+    }
+}
@@ -1,12 +1,12 @@
 mod components;
 
-use self::components::{current_command, mode, vi_bar};
-use crate::{app::App, app::Window};
-use components::{preview, results, search, text_area};
+use self::components::{current_command, history, message, mode, options, status, vi_bar};
+use crate::{app::App, app::Window, config::Theme};
+use components::{changes, diff, journal, preview, results, search, text_area};
 use ratatui::{
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Margin, Rect},
     style::Color,
-    widgets::ListState,
+    widgets::{Clear, ListState},
     Frame,
 };
 
@@ -16,29 +16,29 @@ struct Colors {
 }
 
 impl Colors {
-    fn new(chosen_window: &Window) -> Self {
+    fn new(chosen_window: &Window, theme: &Theme) -> Self {
         Self {
             search: if chosen_window == &Window::Search {
-                Color::LightBlue
+                theme.focus
             } else {
-                Color::Blue
+                theme.border
             },
             command: if chosen_window == &Window::Command {
-                Color::LightBlue
+                theme.focus
             } else {
-                Color::Blue
+                theme.border
             },
         }
     }
 }
 
 pub fn render(app: &mut App, frame: &mut Frame) {
-    let colors = Colors::new(&app.window);
+    let colors = Colors::new(&app.window, &app.config.theme);
     let mut result_state = ListState::default();
     result_state.select(Some(app.search.scroll));
 
     let mut options_state = ListState::default();
-    options_state.select(Some(app.search.scroll));
+    options_state.select(Some(app.option));
 
     let areas = Layout::default()
         .direction(Direction::Vertical)
@@ -51,8 +51,8 @@ pub fn render(app: &mut App, frame: &mut Frame) {
     let columns = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([
-            Constraint::Percentage(50), // Search, and results column
-            Constraint::Percentage(35), // Preview column
+            Constraint::Percentage(app.config.layout.results), // Search, and results column
+            Constraint::Percentage(app.config.layout.preview), // Preview column
         ])
         .split(areas[0]);
 
@@ -66,9 +66,56 @@ pub fn render(app: &mut App, frame: &mut Frame) {
 
     frame.render_stateful_widget(results(app), rows[1], &mut result_state);
     frame.render_widget(text_area(app).widget(), rows[0]);
-    frame.render_widget(search(colors.search), rows[0]);
-    frame.render_widget(preview(app), columns[1]);
+    frame.render_widget(search(colors.search, &app.config.theme), rows[0]);
+    match (&app.window, &app.review) {
+        (Window::Review, Some(review)) => {
+            let (diff, selected) = diff(review, &app.config.theme);
+            let mut diff_state = ListState::default();
+            diff_state.select(Some(selected));
+            frame.render_stateful_widget(diff, columns[1], &mut diff_state);
+        }
+        (Window::Journal, _) => {
+            if let Some(batch) = app.journal.batches().nth(app.journal.selected) {
+                frame.render_widget(changes(batch, &app.config.theme), columns[1]);
+            }
+        }
+        _ => frame.render_widget(preview(app), columns[1]),
+    }
     frame.render_widget(vi_bar(app, colors.command).widget(), areas[1]);
     frame.render_widget(mode(app), areas[1]);
+    frame.render_widget(message(app), areas[1]);
     frame.render_widget(current_command(app), areas[1]);
+    frame.render_widget(status(app), areas[1]);
+
+    if app.window == Window::Options {
+        let area = centered(areas[0], 48, crate::options::names().count() as u16 + 2);
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(options(app), area, &mut options_state);
+    }
+    if app.window == Window::Journal {
+        let area = rows[1].inner(&Margin::new(2, 1));
+        let mut journal_state = ListState::default();
+        journal_state.select(Some(app.journal.selected));
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(journal(app), area, &mut journal_state);
+    }
+    if app.window == Window::History {
+        let area = rows[1].inner(&Margin::new(2, 1));
+        let mut history_state = ListState::default();
+        history_state.select(Some(app.search_history.selected));
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(history(app), area, &mut history_state);
+    }
+}
+
+/// A `width` by `height` rectangle in the middle of `area`, shrunk to fit it.
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
 }
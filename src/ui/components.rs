@@ -1,34 +1,85 @@
-use crate::app::{App, Mode, Window};
+use crate::app::{App, Matcher, Message, Mode, Source, Window};
+use crate::config::Theme;
+use crate::journal::Batch;
+use crate::options::{self, Value};
+use crate::patch;
+use crate::substitute::Review;
 use ratatui::{
     layout::Alignment,
     style::{Color, Modifier, Style, Stylize},
-    text::Span,
+    text::{Line, Span},
     widgets::{Block, BorderType, Borders, List, ListItem, Padding, Paragraph},
 };
+use std::{
+    ops::Range,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use tui_textarea::{CursorMove, TextArea};
 
-pub fn text_area(app: &mut App) -> TextArea {
+fn match_style(theme: &Theme) -> Style {
+    Style::default()
+        .fg(theme.matched)
+        .add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+}
+
+/// Splits `text` into spans, styling the byte `ranges` with [`match_style`].
+fn highlight<'a>(text: &'a str, ranges: &[Range<usize>], theme: &Theme) -> Vec<Span<'a>> {
+    let mut ranges = ranges.to_vec();
+    ranges.sort_by_key(|range| range.start);
+
+    let mut spans = Vec::new();
+    let mut position = 0;
+    for range in ranges {
+        let start = range.start.max(position);
+        let (Some(before), Some(matched)) = (text.get(position..start), text.get(start..range.end))
+        else {
+            continue;
+        };
+
+        spans.push(Span::raw(before));
+        spans.push(Span::styled(matched, match_style(theme)));
+        position = range.end;
+    }
+    spans.push(Span::raw(text.get(position..).unwrap_or_default()));
+
+    spans
+}
+
+pub fn text_area(app: &mut App) -> TextArea<'_> {
     let mut text_area = TextArea::default();
     text_area.set_cursor_line_style(Style::default());
-    text_area.set_style(Style::default().fg(Color::LightRed));
+    text_area.set_style(Style::default().fg(app.config.theme.query));
     text_area.set_block(
         Block::default()
             .borders(Borders::ALL)
             .padding(Padding::new(3, 0, 0, 0)),
     );
     text_area.insert_str(app.search.query.iter().collect::<String>());
+    // The cursor draws the last selected character itself, so the selection stops short
+    // of it on whichever side it's on.
+    if app.search.mode == Mode::Visual {
+        let anchor = app.search.query_anchor;
+        let start = if anchor <= app.search.cursor {
+            anchor
+        } else {
+            anchor + 1
+        };
+        text_area.move_cursor(CursorMove::Jump(0, start as u16));
+        text_area.start_selection();
+        text_area.set_selection_style(Style::default().add_modifier(Modifier::REVERSED));
+    }
     text_area.move_cursor(CursorMove::Jump(0, app.search.cursor as u16));
     text_area.set_cursor_style(
         Style::default()
-            .fg(Color::LightBlue)
+            .fg(app.config.theme.focus)
             .add_modifier(Modifier::REVERSED),
     );
 
     text_area
 }
 
-pub fn search(color: Color) -> Paragraph<'static> {
-    Paragraph::new(Span::styled(">", Style::default().fg(Color::Magenta)))
+pub fn search(color: Color, theme: &Theme) -> Paragraph<'static> {
+    Paragraph::new(Span::styled(">", Style::default().fg(theme.prompt)))
         .block(
             Block::default()
                 .title(Span::raw(" Search "))
@@ -37,38 +88,257 @@ pub fn search(color: Color) -> Paragraph<'static> {
                 .border_type(BorderType::Rounded)
                 .padding(Padding::new(1, 0, 0, 0)),
         )
-        .style(Style::default().fg(color).bg(Color::Black))
+        .style(Style::default().fg(color).bg(theme.background))
         .alignment(Alignment::Left)
 }
 
 pub fn results<'a>(app: &'a mut App) -> List<'a> {
+    let theme = &app.config.theme;
+    let visual = match app.search.mode {
+        Mode::VisualLine => Some(app.search.visual_range()),
+        _ => None,
+    };
     let items = app
         .search
         .result
         .iter()
-        .map(|item| ListItem::new(Span::from(item)).style(Style::default().fg(Color::White)))
+        .enumerate()
+        .map(|(index, item)| {
+            let edited = app
+                .edit
+                .as_ref()
+                .and_then(|edit| edit.originals.get(&index));
+            let mut spans = vec![match (item.marked, edited) {
+                (_, Some(original)) if original != &item.text => {
+                    Span::styled("~ ", Style::default().fg(theme.focus))
+                }
+                (true, _) => Span::styled("+ ", Style::default().fg(theme.prompt)),
+                (false, _) => Span::raw("  "),
+            }];
+            if app.search.source == Source::Content {
+                spans.push(Span::raw(format!(
+                    "{}:{}:{}:",
+                    item.path.display(),
+                    item.line,
+                    item.column
+                )));
+            }
+            spans.extend(highlight(&item.text, &item.submatches, theme));
+
+            let color = if item.context {
+                theme.muted
+            } else {
+                theme.text
+            };
+            let item = ListItem::new(Line::from(spans)).style(Style::default().fg(color));
+            match &visual {
+                Some(range) if range.contains(&index) => item.bg(theme.muted),
+                _ => item,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let name = match (app.search.source, app.search.matcher) {
+        _ if app.edit.is_some() => "Results [editing]",
+        (Source::Files, _) => "Files",
+        (Source::Stdin, _) => "Filter",
+        (_, Matcher::Regex) => "Results",
+        (_, Matcher::Fuzzy) => "Results [fuzzy]",
+    };
+    let marked = app.search.result.iter().filter(|r| r.marked).count();
+    let matches = app.search.result.iter().filter(|r| !r.context).count();
+    let title = match marked {
+        0 => format!(" {} ({}) ", name, matches),
+        marked => format!(" {} ({}) [{} marked] ", name, matches, marked),
+    };
+
+    List::new(items)
+        .block(
+            Block::default()
+                .title(title)
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded),
+        )
+        .style(Style::default().fg(theme.border).bg(theme.background))
+        .highlight_style(
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(theme.selection),
+        )
+        .highlight_symbol(" > ")
+}
+
+/// The options panel, one row per setting with its group shown on the first.
+pub fn options(app: &mut App) -> List<'static> {
+    let theme = &app.config.theme;
+    let mut items = Vec::new();
+    for (group, names) in options::GROUPS {
+        for (index, name) in names.iter().enumerate() {
+            let group = if index == 0 { group } else { "" };
+            let value = match app.config.get(name) {
+                Some(Value::Bool(true)) => "on".to_string(),
+                Some(Value::Bool(false)) => "off".to_string(),
+                Some(Value::Number(value)) => value.to_string(),
+                Some(Value::Text(value)) if value.is_empty() => "-".to_string(),
+                Some(Value::Text(value)) => value,
+                None => continue,
+            };
+            items.push(ListItem::new(Line::from(vec![
+                Span::styled(format!("{:<9}", group), Style::default().fg(theme.prompt)),
+                Span::styled(format!("{:<19}", name), Style::default().fg(theme.text)),
+                Span::styled(value, Style::default().fg(theme.query)),
+            ])));
+        }
+    }
+
+    List::new(items)
+        .block(
+            Block::default()
+                .title(" Options ")
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded),
+        )
+        .style(Style::default().fg(theme.focus).bg(theme.background))
+        .highlight_style(
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(theme.selection),
+        )
+        .highlight_symbol(" > ")
+}
+
+/// The Ctrl-r popup, listing past queries that match what's typed into it.
+pub fn history<'a>(app: &'a mut App) -> List<'a> {
+    let theme = &app.config.theme;
+    let items = app
+        .search_history
+        .matches()
+        .into_iter()
+        .map(|(entry, ranges)| {
+            ListItem::new(Line::from(highlight(entry, &ranges, theme)))
+                .style(Style::default().fg(theme.text))
+        })
+        .collect::<Vec<_>>();
+
+    List::new(items)
+        .block(
+            Block::default()
+                .title(format!(" History > {} ", app.search_history.filter))
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded),
+        )
+        .style(Style::default().fg(theme.focus).bg(theme.background))
+        .highlight_style(
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(theme.selection),
+        )
+        .highlight_symbol(" > ")
+}
+
+/// How long ago `time`, in seconds since the epoch, was.
+fn ago(time: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    match now.saturating_sub(time) {
+        seconds @ 0..=59 => format!("{}s ago", seconds),
+        seconds @ 60..=3599 => format!("{}m ago", seconds / 60),
+        seconds @ 3600..=86399 => format!("{}h ago", seconds / 3600),
+        seconds => format!("{}d ago", seconds / 86400),
+    }
+}
+
+/// The `:journal` popup, listing past batches of file changes, newest first.
+pub fn journal(app: &App) -> List<'static> {
+    let theme = &app.config.theme;
+    let items = app
+        .journal
+        .batches()
+        .map(|batch| {
+            let (state, style) = if batch.undone {
+                (" undone", Style::default().fg(theme.muted))
+            } else {
+                ("", Style::default().fg(theme.text))
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!("{:>8}  ", ago(batch.time)),
+                    Style::default().fg(theme.prompt),
+                ),
+                Span::raw(format!(
+                    "{}  {}{}",
+                    batch.command,
+                    patch::lines_in_files(batch.lines(), batch.patches.len()),
+                    state
+                )),
+            ]))
+            .style(style)
+        })
         .collect::<Vec<_>>();
 
     List::new(items)
         .block(
             Block::default()
-                .title(" Results ")
+                .title(" Journal ")
                 .title_alignment(Alignment::Center)
                 .borders(Borders::ALL)
                 .border_type(BorderType::Rounded),
         )
-        .style(Style::default().fg(Color::Blue).bg(Color::Black))
+        .style(Style::default().fg(theme.focus).bg(theme.background))
         .highlight_style(
             Style::default()
                 .add_modifier(Modifier::BOLD)
-                .fg(Color::Yellow),
+                .fg(theme.selection),
         )
         .highlight_symbol(" > ")
 }
 
+/// Every line a journal batch changed, as a diff of each file in turn.
+pub fn changes(batch: &Batch, theme: &Theme) -> List<'static> {
+    let mut items = Vec::new();
+    for patch in &batch.patches {
+        items.push(
+            ListItem::new(patch.path.display().to_string()).style(
+                Style::default()
+                    .fg(theme.focus)
+                    .add_modifier(Modifier::BOLD),
+            ),
+        );
+        for hunk in &patch.hunks {
+            items.push(
+                ListItem::new(format!("@@ line {} @@", hunk.line))
+                    .style(Style::default().fg(theme.prompt)),
+            );
+            items.push(
+                ListItem::new(format!("-{}", hunk.before)).style(Style::default().fg(theme.error)),
+            );
+            items.push(
+                ListItem::new(format!("+{}", hunk.after))
+                    .style(Style::default().fg(theme.selection)),
+            );
+        }
+    }
+
+    List::new(items)
+        .block(
+            Block::default()
+                .title(format!(" {} ", batch.command))
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded),
+        )
+        .style(Style::default().fg(theme.focus).bg(theme.background))
+}
+
 pub fn preview<'a>(app: &'a mut App) -> List<'a> {
+    let theme = &app.config.theme;
     List::new(app.search.preview.lines().enumerate().map(|(index, line)| {
-        let item = ListItem::new(Span::from(line).style(Style::default().fg(Color::White)));
+        let ranges = app.search.highlights.get(index).map_or(&[][..], |r| r);
+        let item = ListItem::new(Line::from(highlight(line, ranges, theme)))
+            .style(Style::default().fg(theme.text));
         if index + 1 == app.search.line {
             return item.add_modifier(Modifier::REVERSED);
         }
@@ -82,13 +352,69 @@ pub fn preview<'a>(app: &'a mut App) -> List<'a> {
             .borders(Borders::ALL)
             .border_type(BorderType::Rounded),
     )
-    .style(Style::default().fg(Color::Blue).bg(Color::Black))
+    .style(Style::default().fg(theme.border).bg(theme.background))
 }
 
-pub fn vi_bar(app: &mut App, color: Color) -> TextArea {
+/// The substitutions for the file of the highlighted hunk as a unified diff, along with
+/// the row of that hunk's header.
+pub fn diff(review: &Review, theme: &Theme) -> (List<'static>, usize) {
+    let (file, current) = review.position();
+    let Some(patch) = review.patches.get(file) else {
+        return (List::new(Vec::<ListItem>::new()), 0);
+    };
+
+    let mut items = Vec::new();
+    let mut selected = 0;
+    for (index, hunk) in patch.hunks.iter().enumerate() {
+        let (state, style) = if hunk.accepted {
+            ("", Style::default())
+        } else {
+            (" rejected", Style::default().fg(theme.muted))
+        };
+        let header = ListItem::new(format!("@@ line {}{} @@", hunk.line, state))
+            .style(Style::default().fg(theme.prompt));
+        if index == current {
+            selected = items.len();
+            items.push(header.add_modifier(Modifier::REVERSED));
+        } else {
+            items.push(header);
+        }
+        items.push(
+            ListItem::new(format!("-{}", hunk.before))
+                .style(Style::default().fg(theme.error).patch(style)),
+        );
+        items.push(
+            ListItem::new(format!("+{}", hunk.after))
+                .style(Style::default().fg(theme.selection).patch(style)),
+        );
+    }
+
+    let accepted = patch.hunks.iter().filter(|hunk| hunk.accepted).count();
+    let title = format!(
+        " {} [{}/{} accepted] ({}/{}) ",
+        patch.path.display(),
+        accepted,
+        patch.hunks.len(),
+        file + 1,
+        review.patches.len()
+    );
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title(title)
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded),
+        )
+        .style(Style::default().fg(theme.focus).bg(theme.background));
+
+    (list, selected)
+}
+
+pub fn vi_bar(app: &mut App, color: Color) -> TextArea<'_> {
     let mut text_area = TextArea::default();
     text_area.set_cursor_line_style(Style::default());
-    text_area.set_style(Style::default().fg(Color::White));
+    text_area.set_style(Style::default().fg(app.config.theme.text));
     text_area.set_block(
         Block::default()
             .borders(Borders::BOTTOM)
@@ -98,14 +424,14 @@ pub fn vi_bar(app: &mut App, color: Color) -> TextArea {
     text_area.move_cursor(CursorMove::Jump(0, app.command.cursor as u16));
     text_area.set_cursor_style(
         Style::default()
-            .fg(Color::LightBlue)
+            .fg(app.config.theme.focus)
             .add_modifier(Modifier::REVERSED),
     );
 
     if app.window != Window::Command {
         text_area.set_cursor_style(
             Style::default()
-                .fg(Color::LightBlue)
+                .fg(app.config.theme.focus)
                 .add_modifier(Modifier::HIDDEN),
         );
     }
@@ -120,13 +446,47 @@ pub fn mode(app: &mut App) -> Paragraph<'static> {
     let mode = match app.search.mode {
         Mode::Normal => "NORMAL",
         Mode::Insert => "INSERT",
+        Mode::Replace => "REPLACE",
+        Mode::Visual => "VISUAL",
+        Mode::VisualLine => "V-LINE",
     };
 
     Paragraph::new(mode).block(Block::default().padding(Padding::new(1, 0, 0, 0)))
 }
 
+pub fn message(app: &mut App) -> Paragraph<'static> {
+    // The command line and the history popup take the footer over.
+    let message = match (&app.window, &app.message) {
+        (Window::Command | Window::History, _) | (_, None) => Span::raw(""),
+        (_, Some(Message::Info(text))) => {
+            Span::styled(text.clone(), Style::default().fg(app.config.theme.text))
+        }
+        (_, Some(Message::Error(text))) => {
+            Span::styled(text.clone(), Style::default().fg(app.config.theme.error))
+        }
+    };
+
+    Paragraph::new(message).block(Block::default().padding(Padding::new(9, 0, 0, 0)))
+}
+
 pub fn current_command(app: &mut App) -> Paragraph<'static> {
-    Paragraph::new(app.vi_command.clone())
+    Paragraph::new(app.pending.typed.clone())
         .block(Block::default().padding(Padding::new(0, 10, 0, 0)))
         .alignment(Alignment::Right)
 }
+
+pub fn status(app: &mut App) -> Paragraph<'static> {
+    let status = if app.search.searching {
+        "searching…".to_string()
+    } else if app.search.skipped > 0 {
+        format!("{} non-UTF-8 hits skipped", app.search.skipped)
+    } else {
+        String::new()
+    };
+
+    Paragraph::new(Span::styled(
+        status,
+        Style::default().fg(app.config.theme.muted),
+    ))
+    .alignment(Alignment::Right)
+}
@@ -0,0 +1,802 @@
+use crate::app::{Mode, Search};
+use crate::keymap::{Action, Key};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+/// An `f`, `t`, `F` or `T` search for a character, remembered for `;` and `,`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Find {
+    pub char: char,
+    pub backward: bool,
+    /// Stop next to the character instead of on it.
+    pub till: bool,
+}
+
+impl Find {
+    pub fn reversed(self) -> Self {
+        Self {
+            backward: !self.backward,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    BigWordForward,
+    BigWordBackward,
+    BigWordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    Find(Find),
+    /// `;` and `,`, which don't get stuck next to the character after `t` or `T`.
+    RepeatFind(Find),
+}
+
+impl Motion {
+    /// Whether an operator also covers the character the motion lands on.
+    fn inclusive(self) -> bool {
+        match self {
+            Motion::WordEnd | Motion::BigWordEnd | Motion::LineEnd => true,
+            Motion::Find(find) | Motion::RepeatFind(find) => !find.backward,
+            _ => false,
+        }
+    }
+
+    /// Where the motion lands from `cursor` when repeated `count` times, or `None` when
+    /// it fails like an `f` for a missing character. The result may be one past the
+    /// last character, which only operators can use.
+    pub fn target(self, query: &[char], cursor: usize, count: usize) -> Option<usize> {
+        let repeat = |step: fn(&[char], usize, bool) -> usize, big| {
+            (0..count).fold(cursor, |cursor, _| step(query, cursor, big))
+        };
+
+        Some(match self {
+            Motion::Left => cursor.saturating_sub(count),
+            Motion::Right => (cursor + count).min(query.len()),
+            Motion::WordForward => repeat(next_word, false),
+            Motion::WordBackward => repeat(previous_word, false),
+            Motion::WordEnd => repeat(word_end, false),
+            Motion::BigWordForward => repeat(next_word, true),
+            Motion::BigWordBackward => repeat(previous_word, true),
+            Motion::BigWordEnd => repeat(word_end, true),
+            Motion::LineStart => 0,
+            Motion::FirstNonBlank => query
+                .iter()
+                .position(|c| !c.is_whitespace())
+                .unwrap_or(query.len().saturating_sub(1)),
+            Motion::LineEnd => query.len().saturating_sub(1),
+            Motion::Find(find) => find_char(query, cursor, count, find, false)?,
+            Motion::RepeatFind(find) => find_char(query, cursor, count, find, true)?,
+        })
+    }
+
+    /// The characters an operator combined with this motion acts on.
+    pub fn range(self, query: &[char], cursor: usize, count: usize) -> Option<Range<usize>> {
+        let target = self.target(query, cursor, count)?;
+        let (start, end) = (cursor.min(target), cursor.max(target));
+        if self.inclusive() && !query.is_empty() {
+            Some(start..end + 1)
+        } else {
+            Some(start..end)
+        }
+    }
+}
+
+/// Whitespace, punctuation or keyword characters; a vim `word` is a run of one class,
+/// while a `WORD` is any run of non-blanks.
+fn class(c: char, big: bool) -> u8 {
+    match c {
+        c if c.is_whitespace() => 0,
+        _ if big => 1,
+        c if c.is_alphanumeric() || c == '_' => 2,
+        _ => 1,
+    }
+}
+
+fn next_word(query: &[char], cursor: usize, big: bool) -> usize {
+    let mut index = cursor;
+    if let Some(&c) = query.get(index) {
+        let current = class(c, big);
+        while current != 0 && index < query.len() && class(query[index], big) == current {
+            index += 1;
+        }
+    }
+    while index < query.len() && class(query[index], big) == 0 {
+        index += 1;
+    }
+
+    index
+}
+
+fn previous_word(query: &[char], cursor: usize, big: bool) -> usize {
+    let mut index = cursor.min(query.len());
+    while index > 0 && class(query[index - 1], big) == 0 {
+        index -= 1;
+    }
+    if index == 0 {
+        return 0;
+    }
+
+    let current = class(query[index - 1], big);
+    while index > 0 && class(query[index - 1], big) == current {
+        index -= 1;
+    }
+
+    index
+}
+
+fn word_end(query: &[char], cursor: usize, big: bool) -> usize {
+    let mut index = cursor + 1;
+    while index < query.len() && class(query[index], big) == 0 {
+        index += 1;
+    }
+    if index >= query.len() {
+        return query.len().saturating_sub(1).max(cursor);
+    }
+
+    let current = class(query[index], big);
+    while index + 1 < query.len() && class(query[index + 1], big) == current {
+        index += 1;
+    }
+
+    index
+}
+
+fn find_char(
+    query: &[char],
+    cursor: usize,
+    count: usize,
+    find: Find,
+    repeat: bool,
+) -> Option<usize> {
+    // A repeated `t` starts one further so it doesn't stop in front of the same
+    // character.
+    let skip = 1 + usize::from(repeat && find.till);
+    let mut index = cursor;
+    for _ in 0..count {
+        index = if find.backward {
+            (0..index.checked_sub(skip - 1)?)
+                .rev()
+                .find(|&i| query[i] == find.char)?
+        } else {
+            (index + skip..query.len()).find(|&i| query[i] == find.char)?
+        };
+    }
+
+    Some(match (find.till, find.backward) {
+        (false, _) => index,
+        (true, false) => index - 1,
+        (true, true) => index + 1,
+    })
+}
+
+/// A text object such as `iw`, `a"` or `i(`, named by the character after `i` or `a`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextObject {
+    kind: char,
+    around: bool,
+}
+
+impl TextObject {
+    pub fn new(kind: char, around: bool) -> Option<Self> {
+        match kind {
+            'w' | 'W' | '"' | '\'' | '`' | '(' | ')' | 'b' | '[' | ']' | '{' | '}' | 'B' | '<'
+            | '>' => Some(Self { kind, around }),
+            _ => None,
+        }
+    }
+
+    /// The characters the object covers around `cursor`, if it's there at all.
+    /// With a count, words are taken `count` at a time and brackets `count` levels out.
+    pub fn range(self, query: &[char], cursor: usize, count: usize) -> Option<Range<usize>> {
+        if cursor >= query.len() {
+            return None;
+        }
+
+        match self.kind {
+            'w' => Some(self.word(query, cursor, count, false)),
+            'W' => Some(self.word(query, cursor, count, true)),
+            '"' | '\'' | '`' => self.quote(query, cursor),
+            '(' | ')' | 'b' => self.pair(query, cursor, count, ('(', ')')),
+            '[' | ']' => self.pair(query, cursor, count, ('[', ']')),
+            '{' | '}' | 'B' => self.pair(query, cursor, count, ('{', '}')),
+            _ => self.pair(query, cursor, count, ('<', '>')),
+        }
+    }
+
+    fn word(self, query: &[char], cursor: usize, count: usize, big: bool) -> Range<usize> {
+        let run = |index: usize| {
+            let kind = class(query[index], big);
+            let mut range = index..index + 1;
+            while range.start > 0 && class(query[range.start - 1], big) == kind {
+                range.start -= 1;
+            }
+            while range.end < query.len() && class(query[range.end], big) == kind {
+                range.end += 1;
+            }
+            range
+        };
+        // Grows `range` by the run after it if that run is (or isn't) blank.
+        let extend = |range: &mut Range<usize>, blank: bool| {
+            let next = range.end < query.len() && (class(query[range.end], big) == 0) == blank;
+            if next {
+                range.end = run(range.end).end;
+            }
+            next
+        };
+
+        let start = run(cursor).start;
+        let mut range = start..start;
+        if !self.around {
+            for _ in 0..count {
+                if range.end < query.len() {
+                    range.end = run(range.end).end;
+                }
+            }
+            return range;
+        }
+
+        // `aw` is a word and the blanks after it, or blanks and the word after them when
+        // starting on blanks. Without blanks after, it takes the ones before instead.
+        let on_blank = class(query[cursor], big) == 0;
+        let mut trailing = on_blank;
+        for _ in 0..count {
+            if on_blank {
+                extend(&mut range, true);
+                extend(&mut range, false);
+            } else {
+                extend(&mut range, false);
+                trailing = extend(&mut range, true);
+            }
+        }
+        if !trailing && range.start > 0 && class(query[range.start - 1], big) == 0 {
+            range.start = run(range.start - 1).start;
+        }
+
+        range
+    }
+
+    fn quote(self, query: &[char], cursor: usize) -> Option<Range<usize>> {
+        let quotes = query
+            .iter()
+            .enumerate()
+            .filter(|&(_, &c)| c == self.kind)
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        // Quotes pair up from the start of the line; use the pair around the cursor or
+        // the next one after it.
+        let (open, close) = quotes
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|&(_, close)| close >= cursor)?;
+
+        if !self.around {
+            return Some(open + 1..close);
+        }
+        let mut end = close + 1;
+        while end < query.len() && query[end].is_whitespace() {
+            end += 1;
+        }
+
+        Some(open..end)
+    }
+
+    fn pair(
+        self,
+        query: &[char],
+        cursor: usize,
+        count: usize,
+        (open, close): (char, char),
+    ) -> Option<Range<usize>> {
+        let (mut depth, mut levels) = (0, count);
+        let start = (0..=cursor).rev().find(|&i| {
+            match query[i] {
+                c if c == close && i != cursor => depth += 1,
+                c if c == open && depth == 0 => {
+                    levels -= 1;
+                    return levels == 0;
+                }
+                c if c == open => depth -= 1,
+                _ => {}
+            }
+            false
+        })?;
+
+        let mut depth = 0;
+        let end = (start + 1..query.len()).find(|&i| {
+            match query[i] {
+                c if c == open => depth += 1,
+                c if c == close && depth == 0 => return true,
+                c if c == close => depth -= 1,
+                _ => {}
+            }
+            false
+        })?;
+
+        if self.around {
+            Some(start..end + 1)
+        } else {
+            Some(start + 1..end)
+        }
+    }
+}
+
+/// Moves the query cursor with `motion`, staying on a character outside insert mode.
+pub fn move_cursor(search: &mut Search, motion: Motion, count: usize) {
+    let last = match search.mode {
+        Mode::Insert => search.query.len(),
+        _ => search.query.len().saturating_sub(1),
+    };
+    if let Some(target) = motion.target(&search.query, search.cursor, count) {
+        search.cursor = target.min(last);
+    }
+}
+
+/// Applies `operator` over `motion` from the cursor and returns the text it covered,
+/// or `None` when the motion failed. Like vim, `cw` on a word leaves the whitespace
+/// after it alone.
+pub fn operate(
+    search: &mut Search,
+    operator: Operator,
+    motion: Motion,
+    count: usize,
+) -> Option<String> {
+    let mut range = motion.range(&search.query, search.cursor, count)?;
+    let on_word = search
+        .query
+        .get(search.cursor)
+        .is_some_and(|c| !c.is_whitespace());
+    let word = matches!(motion, Motion::WordForward | Motion::BigWordForward);
+    if operator == Operator::Change && word && on_word {
+        while range.end > range.start + 1 && search.query[range.end - 1].is_whitespace() {
+            range.end -= 1;
+        }
+    }
+
+    Some(apply(search, operator, range))
+}
+
+/// Applies `operator` to a text object around the cursor, doing nothing if there's none.
+pub fn operate_object(
+    search: &mut Search,
+    operator: Operator,
+    object: TextObject,
+    count: usize,
+) -> Option<String> {
+    let range = object.range(&search.query, search.cursor, count)?;
+    Some(apply(search, operator, range))
+}
+
+/// Applies `operator` to the whole query, as in `dd`, `cc` and `yy`. Unlike other yanks,
+/// `yy` leaves the cursor where it is.
+pub fn operate_line(search: &mut Search, operator: Operator) -> String {
+    match operator {
+        Operator::Yank => search.query.iter().collect(),
+        operator => apply(search, operator, 0..search.query.len()),
+    }
+}
+
+/// Applies `operator` to `range` of the query, like a visual selection, and returns the
+/// text it covered.
+pub fn apply(search: &mut Search, operator: Operator, range: Range<usize>) -> String {
+    let len = search.query.len();
+    let range = range.start.min(len)..range.end.min(len);
+    let text = search.query[range.clone()].iter().collect();
+    if operator == Operator::Yank {
+        search.cursor = range.start.min(len.saturating_sub(1));
+        return text;
+    }
+
+    search.query.drain(range.clone());
+    search.cursor = range.start;
+    match operator {
+        Operator::Change => search.mode = Mode::Insert,
+        _ => search.cursor = search.cursor.min(search.query.len().saturating_sub(1)),
+    }
+
+    text
+}
+
+/// Overwrites `count` characters from the cursor with `c`, or none if there aren't that
+/// many, leaving the cursor on the last one.
+pub fn replace(search: &mut Search, c: char, count: usize) -> bool {
+    let range = search.cursor..search.cursor + count;
+    if range.end > search.query.len() {
+        return false;
+    }
+
+    search.query[range.clone()].fill(c);
+    search.cursor = range.end - 1;
+    true
+}
+
+/// Inserts `text` `count` times after or before the cursor, leaving the cursor on the
+/// last inserted character.
+pub fn put(search: &mut Search, text: &str, after: bool, count: usize) {
+    let text = text.repeat(count).chars().collect::<Vec<_>>();
+    if text.is_empty() {
+        return;
+    }
+
+    let at = if after && !search.query.is_empty() {
+        search.cursor + 1
+    } else {
+        search.cursor
+    }
+    .min(search.query.len());
+    search.cursor = at + text.len() - 1;
+    search.query.splice(at..at, text);
+}
+
+/// Yanked and deleted text by register name. Yanks go to `0`, deletes to `-`, and both to
+/// the unnamed `"` register unless they went to the black hole `_`. Uppercase names
+/// append to their lowercase register.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Registers(HashMap<char, String>);
+
+impl Registers {
+    pub fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || matches!(name, '"' | '-' | '_')
+    }
+
+    pub fn store(&mut self, name: Option<char>, text: String, operator: Operator) {
+        let name = match name {
+            Some('_') => return,
+            Some('"') | None if operator == Operator::Yank => '0',
+            Some('"') | None => '-',
+            Some(name) => name,
+        };
+        let register = self.0.entry(name.to_ascii_lowercase()).or_default();
+        if name.is_ascii_uppercase() {
+            register.push_str(&text);
+        } else {
+            *register = text;
+        }
+
+        let text = register.clone();
+        self.0.insert('"', text);
+    }
+
+    pub fn get(&self, name: Option<char>) -> Option<&str> {
+        let name = name.unwrap_or('"').to_ascii_lowercase();
+        self.0.get(&name).map(String::as_str)
+    }
+}
+
+type Snapshot = (Vec<char>, usize);
+
+/// Undo and redo states of the query, plus the keys of the last change for `.`.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// The query before the command being typed, and the keys typed for it so far.
+    current: Option<(Snapshot, Vec<Key>)>,
+    pub last_change: Vec<Key>,
+}
+
+impl History {
+    /// Records `key` as part of the command in progress, starting one if needed.
+    pub fn record(&mut self, search: &Search, key: Key) {
+        self.current
+            .get_or_insert_with(|| ((search.query.clone(), search.cursor), Vec::new()))
+            .1
+            .push(key);
+    }
+
+    /// Ends the command in progress, keeping it as an undo step and for `.` if it
+    /// changed the query.
+    pub fn commit(&mut self, search: &Search) -> bool {
+        let Some((before, keys)) = self.current.take() else {
+            return false;
+        };
+        if before.0 == search.query {
+            return false;
+        }
+
+        self.undo.push(before);
+        self.redo.clear();
+        self.last_change = keys;
+        true
+    }
+
+    /// The keys of the last change, which also become the keys of the command in
+    /// progress so that repeating a `.` repeats the original change.
+    pub fn repeat(&mut self) -> Vec<Key> {
+        if let Some((_, keys)) = &mut self.current {
+            keys.clone_from(&self.last_change);
+        }
+        self.last_change.clone()
+    }
+
+    /// Forgets the command in progress, so undoing isn't itself recorded as a change.
+    pub fn forget(&mut self) {
+        self.current = None;
+    }
+
+    pub fn undo(&mut self, search: &mut Search) -> bool {
+        Self::step(&mut self.undo, &mut self.redo, search)
+    }
+
+    pub fn redo(&mut self, search: &mut Search) -> bool {
+        Self::step(&mut self.redo, &mut self.undo, search)
+    }
+
+    fn step(from: &mut Vec<Snapshot>, to: &mut Vec<Snapshot>, search: &mut Search) -> bool {
+        let Some((query, cursor)) = from.pop() else {
+            return false;
+        };
+        to.push((std::mem::replace(&mut search.query, query), search.cursor));
+        search.cursor = cursor.min(search.query.len().saturating_sub(1));
+        true
+    }
+}
+
+/// A normal mode command still being typed: its count, a pending operator and keys that
+/// may yet grow into a longer mapping.
+#[derive(Debug, Default)]
+pub struct Pending {
+    pub keys: Vec<Key>,
+    pub count: Option<usize>,
+    /// The operator waiting for its motion, with the count typed before it.
+    pub operator: Option<(Operator, usize)>,
+    /// A command like `f` waiting for the character it takes.
+    pub argument: Option<Action>,
+    /// The register picked with `"` for the next yank, delete or put.
+    pub register: Option<char>,
+    /// Everything typed since the last command finished, shown in the footer.
+    pub typed: String,
+    last: Option<Instant>,
+}
+
+impl Pending {
+    pub fn push(&mut self, key: Key) {
+        self.keys.push(key);
+        self.typed.push_str(&key.to_string());
+        self.last = Some(Instant::now());
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.keys.is_empty()
+            && self.count.is_none()
+            && self.operator.is_none()
+            && self.argument.is_none()
+            && self.register.is_none()
+    }
+
+    /// Whether what's pending has waited for `timeout`: keys that could still grow into
+    /// a longer mapping, or a count, operator or register nothing followed. Only a
+    /// command like `f` waits for its character as long as it takes.
+    pub fn expired(&self, timeout: Duration) -> bool {
+        !self.is_idle()
+            && self.argument.is_none()
+            && self.last.is_some_and(|last| last.elapsed() >= timeout)
+    }
+
+    /// Starts the timeout over for whatever is still pending.
+    pub fn restart(&mut self) {
+        self.last = Some(Instant::now());
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::parse_keys;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    fn find(char: char, backward: bool, till: bool) -> Find {
+        Find {
+            char,
+            backward,
+            till,
+        }
+    }
+
+    fn search(query: &str, cursor: usize) -> Search {
+        Search {
+            query: chars(query),
+            cursor,
+            ..Search::default()
+        }
+    }
+
+    #[test]
+    fn word_motions() {
+        //                 0123456789012345
+        let query = chars("foo bar.baz  qux");
+        let target = |motion: Motion, cursor, count| motion.target(&query, cursor, count);
+        assert_eq!(target(Motion::WordForward, 0, 1), Some(4));
+        assert_eq!(target(Motion::WordForward, 0, 2), Some(7));
+        assert_eq!(target(Motion::WordForward, 0, 3), Some(8));
+        assert_eq!(target(Motion::BigWordForward, 0, 2), Some(13));
+        assert_eq!(target(Motion::WordEnd, 0, 1), Some(2));
+        assert_eq!(target(Motion::WordEnd, 4, 1), Some(6));
+        assert_eq!(target(Motion::BigWordEnd, 4, 1), Some(10));
+        assert_eq!(target(Motion::WordBackward, 13, 1), Some(8));
+        assert_eq!(target(Motion::BigWordBackward, 13, 1), Some(4));
+        // Running off either end stops there.
+        assert_eq!(target(Motion::WordForward, 13, 5), Some(16));
+        assert_eq!(target(Motion::WordBackward, 2, 5), Some(0));
+    }
+
+    #[test]
+    fn line_motions() {
+        let query = chars("  foo ");
+        assert_eq!(Motion::LineStart.target(&query, 4, 1), Some(0));
+        assert_eq!(Motion::FirstNonBlank.target(&query, 4, 1), Some(2));
+        assert_eq!(Motion::LineEnd.target(&query, 0, 1), Some(5));
+        assert_eq!(Motion::Left.target(&query, 1, 3), Some(0));
+        assert_eq!(Motion::Right.target(&query, 4, 3), Some(6));
+    }
+
+    #[test]
+    fn find_motions() {
+        //                 0123456789
+        let query = chars("banana bar");
+        let target = |find, cursor, count| Motion::Find(find).target(&query, cursor, count);
+        assert_eq!(target(find('a', false, false), 0, 1), Some(1));
+        assert_eq!(target(find('a', false, false), 0, 3), Some(5));
+        assert_eq!(target(find('b', false, true), 0, 1), Some(6));
+        assert_eq!(target(find('n', true, false), 5, 1), Some(4));
+        assert_eq!(target(find('n', true, true), 5, 1), Some(5));
+        assert_eq!(target(find('z', false, false), 0, 1), None);
+        assert_eq!(target(find('a', false, false), 0, 9), None);
+
+        // `;` after `t` moves on rather than sticking in front of the same character.
+        let till = find('a', false, true);
+        assert_eq!(Motion::Find(till).target(&query, 1, 1), Some(2));
+        assert_eq!(Motion::Find(till).target(&query, 2, 1), Some(2));
+        assert_eq!(Motion::RepeatFind(till).target(&query, 2, 1), Some(4));
+    }
+
+    #[test]
+    fn operator_ranges() {
+        let query = chars("foo bar baz");
+        let range = |motion: Motion, cursor| motion.range(&query, cursor, 1);
+        assert_eq!(range(Motion::WordForward, 0), Some(0..4));
+        assert_eq!(range(Motion::WordEnd, 0), Some(0..3));
+        assert_eq!(range(Motion::LineEnd, 4), Some(4..11));
+        assert_eq!(range(Motion::WordBackward, 4), Some(0..4));
+        assert_eq!(range(Motion::Find(find('a', false, false)), 0), Some(0..6));
+        assert_eq!(range(Motion::Find(find('o', true, false)), 4), Some(2..4));
+    }
+
+    #[test]
+    fn word_objects() {
+        //                 0123456789
+        let query = chars("foo  bar.x");
+        let range = |kind, around, cursor, count| {
+            TextObject::new(kind, around)
+                .unwrap()
+                .range(&query, cursor, count)
+        };
+        assert_eq!(range('w', false, 1, 1), Some(0..3));
+        assert_eq!(range('w', true, 1, 1), Some(0..5));
+        assert_eq!(range('w', false, 3, 1), Some(3..5));
+        assert_eq!(range('w', true, 3, 1), Some(3..8));
+        assert_eq!(range('w', false, 0, 3), Some(0..8));
+        assert_eq!(range('W', false, 6, 1), Some(5..10));
+        // At the end without blanks after, `aw` takes the ones before.
+        assert_eq!(range('W', true, 6, 1), Some(3..10));
+        assert_eq!(range('w', false, 10, 1), None);
+    }
+
+    #[test]
+    fn quote_objects() {
+        //                   01234567890123456
+        let query = chars(r#"say "hi there" now"#);
+        let object = |around| TextObject::new('"', around).unwrap();
+        assert_eq!(object(false).range(&query, 6, 1), Some(5..13));
+        assert_eq!(object(true).range(&query, 6, 1), Some(4..15));
+        // Before any quote, the next pair is used.
+        assert_eq!(object(false).range(&query, 0, 1), Some(5..13));
+        assert_eq!(object(false).range(&query, 16, 1), None);
+    }
+
+    #[test]
+    fn bracket_objects() {
+        //                 012345678
+        let query = chars("f(a, (b))");
+        let range = |kind, around, cursor, count| {
+            TextObject::new(kind, around)
+                .unwrap()
+                .range(&query, cursor, count)
+        };
+        assert_eq!(range('(', false, 6, 1), Some(6..7));
+        assert_eq!(range('b', true, 6, 1), Some(5..8));
+        assert_eq!(range(')', false, 6, 2), Some(2..8));
+        assert_eq!(range('(', false, 2, 1), Some(2..8));
+        // On the closing bracket itself.
+        assert_eq!(range('(', false, 7, 1), Some(6..7));
+        assert_eq!(range('(', false, 0, 1), None);
+        assert_eq!(range('[', false, 6, 1), None);
+        assert!(TextObject::new('x', false).is_none());
+    }
+
+    #[test]
+    fn change_word_keeps_the_blank_after_it() {
+        let mut query = search("foo bar", 0);
+        let changed = operate(&mut query, Operator::Change, Motion::WordForward, 1);
+        assert_eq!(changed.as_deref(), Some("foo"));
+        assert_eq!(query.query, chars(" bar"));
+        assert_eq!((query.cursor, query.mode), (0, Mode::Insert));
+
+        let mut query = search("foo bar", 0);
+        operate(&mut query, Operator::Delete, Motion::WordForward, 1);
+        assert_eq!(query.query, chars("bar"));
+    }
+
+    #[test]
+    fn delete_at_the_end_keeps_the_cursor_on_the_query() {
+        let mut query = search("foo bar", 4);
+        operate(&mut query, Operator::Delete, Motion::LineEnd, 1);
+        assert_eq!(query.query, chars("foo "));
+        assert_eq!(query.cursor, 3);
+
+        let mut query = search("foo bar", 4);
+        let yanked = operate_line(&mut query, Operator::Yank);
+        assert_eq!((yanked.as_str(), query.cursor), ("foo bar", 4));
+    }
+
+    #[test]
+    fn pending_keys_time_out() {
+        let mut pending = Pending::default();
+        assert!(!pending.expired(Duration::ZERO));
+
+        pending.push(parse_keys("g", &[]).unwrap()[0]);
+        assert!(pending.expired(Duration::ZERO));
+        assert!(!pending.expired(Duration::from_secs(60)));
+
+        // `f` waits for its character however long it takes.
+        pending.argument = Some(Action::FindForward);
+        assert!(!pending.expired(Duration::ZERO));
+    }
+
+    #[test]
+    fn counts_and_operators_time_out() {
+        let mut pending = Pending::default();
+        pending.push(parse_keys("5", &[]).unwrap()[0]);
+        pending.keys.clear();
+        pending.count = Some(5);
+        assert!(pending.expired(Duration::ZERO));
+        assert!(!pending.expired(Duration::from_secs(60)));
+
+        pending.clear();
+        pending.push(parse_keys("d", &[]).unwrap()[0]);
+        pending.keys.clear();
+        pending.operator = Some((Operator::Delete, 1));
+        assert!(pending.expired(Duration::ZERO));
+
+        // A `dt` still waits for its character.
+        pending.argument = Some(Action::TillForward);
+        assert!(!pending.expired(Duration::ZERO));
+    }
+}
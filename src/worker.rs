@@ -0,0 +1,352 @@
+use crate::{
+    app::{Match, Source},
+    event::Event,
+    fuzzy,
+};
+use serde::{de::IgnoredAny, Deserialize};
+use std::{
+    ffi::OsString,
+    io::{self, Read},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+};
+
+type Slot = Arc<Mutex<Option<Child>>>;
+
+/// Runs `rg` on a background thread and streams its output back as [`Event::Search`]
+/// batches while the child is still running.
+///
+/// Only one search is in flight at a time: spawning a new one kills the previous
+/// child, and every event carries the id of the search that produced it so late
+/// results from a cancelled search can be told apart and dropped.
+#[derive(Debug, Default)]
+pub struct Worker {
+    sender: Option<mpsc::Sender<Event>>,
+    id: Arc<AtomicU64>,
+    child: Slot,
+}
+
+impl Worker {
+    pub fn connect(&mut self, sender: mpsc::Sender<Event>) {
+        self.sender = Some(sender);
+    }
+
+    pub fn is_current(&self, id: u64) -> bool {
+        self.id.load(Ordering::Relaxed) == id
+    }
+
+    fn sender(&self) -> anyhow::Result<mpsc::Sender<Event>> {
+        match &self.sender {
+            Some(sender) => Ok(sender.clone()),
+            None => anyhow::bail!("search worker is not connected to the event loop"),
+        }
+    }
+
+    /// Spawns `rg` with `args`, parsing its output as `source` produces it. When `fuzzy`
+    /// is set, every hit is rescored against it and hits that don't fuzzy-match are
+    /// dropped before being posted.
+    pub fn spawn(
+        &mut self,
+        source: Source,
+        args: Vec<OsString>,
+        fuzzy: Option<String>,
+    ) -> anyhow::Result<u64> {
+        self.cancel();
+        let sender = self.sender()?;
+
+        let mut child = Command::new("rg")
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let id = self.id.load(Ordering::Relaxed);
+        let slot = Arc::new(Mutex::new(Some(child)));
+        self.child = slot.clone();
+
+        thread::spawn(move || {
+            let mut buffer = vec![0; 64 * 1024];
+            let mut pending = Vec::new();
+
+            // Every read hands over whatever rg has flushed so far, so complete lines are
+            // posted as one batch per read instead of one event per line.
+            loop {
+                let read = match stdout.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                pending.extend_from_slice(&buffer[..read]);
+
+                let Some(end) = pending.iter().rposition(|&byte| byte == b'\n') else {
+                    continue;
+                };
+                let lines = pending.drain(..=end).collect::<Vec<_>>();
+                let (results, skipped) = parse(source, &lines);
+                let event = Event::Search {
+                    id,
+                    results: score(source, results, fuzzy.as_deref()),
+                    skipped,
+                    done: false,
+                };
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+
+            // An empty slot means the search was cancelled and the child already reaped.
+            let Some(mut child) = slot.lock().expect("worker slot poisoned").take() else {
+                return;
+            };
+            let _ = child.wait();
+
+            let (results, skipped) = parse(source, &pending);
+            let _ = sender.send(Event::Search {
+                id,
+                results: score(source, results, fuzzy.as_deref()),
+                skipped,
+                done: true,
+            });
+        });
+
+        Ok(id)
+    }
+
+    /// Fuzzy-filters in-memory `lines` against `pattern` in batches, stopping early once
+    /// a newer search supersedes this one.
+    pub fn filter(&mut self, lines: Arc<Vec<String>>, pattern: String) -> anyhow::Result<u64> {
+        self.cancel();
+        let sender = self.sender()?;
+        let current = self.id.clone();
+        let id = current.load(Ordering::Relaxed);
+
+        thread::spawn(move || {
+            let mut chunks = lines.chunks(4096).enumerate().peekable();
+            while let Some((index, chunk)) = chunks.next() {
+                if current.load(Ordering::Relaxed) != id {
+                    return;
+                }
+
+                let results = chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(line, text)| Match {
+                        path: PathBuf::new(),
+                        line: index * 4096 + line + 1,
+                        column: 1,
+                        text: text.clone(),
+                        submatches: Vec::new(),
+                        score: 0,
+                        marked: false,
+                        context: false,
+                    })
+                    .collect();
+                let event = Event::Search {
+                    id,
+                    results: score(Source::Stdin, results, Some(&pattern)),
+                    skipped: 0,
+                    done: chunks.peek().is_none(),
+                };
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+
+            if lines.is_empty() {
+                let _ = sender.send(Event::Search {
+                    id,
+                    results: Vec::new(),
+                    skipped: 0,
+                    done: true,
+                });
+            }
+        });
+
+        Ok(id)
+    }
+
+    /// Kills the in-flight `rg` process, if any, and invalidates its pending results.
+    pub fn cancel(&mut self) {
+        self.id.fetch_add(1, Ordering::Relaxed);
+        if let Some(mut child) = self.child.lock().expect("worker slot poisoned").take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+enum Message {
+    Begin(IgnoredAny),
+    End(IgnoredAny),
+    /// Lines around a match with `--context`, listed among the matches as `context`.
+    Context(MatchData),
+    Summary(IgnoredAny),
+    Match(MatchData),
+}
+
+#[derive(Deserialize)]
+struct MatchData {
+    path: Data,
+    lines: Data,
+    line_number: Option<usize>,
+    submatches: Vec<SubmatchData>,
+}
+
+#[derive(Deserialize)]
+struct SubmatchData {
+    start: usize,
+    end: usize,
+}
+
+/// Either `{"text": ...}` or, for data that isn't valid UTF-8, `{"bytes": ...}`.
+#[derive(Deserialize)]
+struct Data {
+    text: Option<String>,
+}
+
+/// The results in `bytes`, along with how many hits had to be skipped.
+fn parse(source: Source, bytes: &[u8]) -> (Vec<Match>, usize) {
+    match source {
+        Source::Content => parse_matches(bytes),
+        Source::Files => (parse_files(bytes), 0),
+        Source::Stdin => unreachable!("stdin lines are filtered in-process"),
+    }
+}
+
+/// Parses `rg --files` output, one path per line.
+fn parse_files(bytes: &[u8]) -> Vec<Match> {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .map(|line| Match {
+            path: line.into(),
+            line: 1,
+            column: 1,
+            text: line.to_string(),
+            submatches: Vec::new(),
+            score: 0,
+            marked: false,
+            context: false,
+        })
+        .collect()
+}
+
+/// Parses `rg --json` output, skipping everything but match and context messages. Those
+/// whose path or line isn't valid UTF-8 are left out too, and counted.
+fn parse_matches(bytes: &[u8]) -> (Vec<Match>, usize) {
+    let mut skipped = 0;
+    let results = bytes
+        .split(|&byte| byte == b'\n')
+        .filter_map(|line| match serde_json::from_slice(line).ok()? {
+            Message::Match(data) => Some((data, false)),
+            Message::Context(data) => Some((data, true)),
+            _ => None,
+        })
+        .filter_map(|(data, context)| {
+            let result = to_match(data, context);
+            skipped += usize::from(result.is_none());
+            result
+        })
+        .collect();
+
+    (results, skipped)
+}
+
+fn to_match(data: MatchData, context: bool) -> Option<Match> {
+    let text = data.lines.text?;
+    let text = text.strip_suffix('\n').unwrap_or(&text);
+    let text = text.strip_suffix('\r').unwrap_or(text).to_string();
+    // Offsets can run into the line ending that was just taken off.
+    let submatches = data
+        .submatches
+        .into_iter()
+        .map(|submatch| {
+            let end = submatch.end.min(text.len());
+            submatch.start.min(end)..end
+        })
+        .collect::<Vec<_>>();
+
+    Some(Match {
+        path: data.path.text?.into(),
+        line: data.line_number?,
+        column: submatches.first().map_or(1, |submatch| submatch.start + 1),
+        text,
+        submatches,
+        score: 0,
+        marked: false,
+        context,
+    })
+}
+
+fn score(source: Source, results: Vec<Match>, pattern: Option<&str>) -> Vec<Match> {
+    let Some(pattern) = pattern else {
+        return results;
+    };
+
+    let mut scorer = fuzzy::Scorer::new(pattern);
+    results
+        .into_iter()
+        .filter_map(|mut result| {
+            let (score, ranges) = scorer.matches(&result.text)?;
+            if source != Source::Files {
+                result.column = ranges.first().map_or(1, |range| range.start + 1);
+            }
+            result.submatches = ranges;
+            result.score = score;
+            Some(result)
+        })
+        .collect()
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_context_lines() {
+        let output = [
+            r#"{"type":"begin","data":{"path":{"text":"a.rs"}}}"#,
+            r#"{"type":"context","data":{"path":{"text":"a.rs"},"lines":{"text":"before\n"},"line_number":1,"absolute_offset":0,"submatches":[]}}"#,
+            r#"{"type":"match","data":{"path":{"text":"a.rs"},"lines":{"text":"a foo\r\n"},"line_number":2,"absolute_offset":7,"submatches":[{"match":{"text":"foo"},"start":2,"end":5}]}}"#,
+            r#"{"type":"end","data":{}}"#,
+        ]
+        .join("\n");
+
+        let (results, skipped) = parse_matches(output.as_bytes());
+        assert_eq!((results.len(), skipped), (2, 0));
+        assert!(results[0].context);
+        assert_eq!((results[0].line, results[0].text.as_str()), (1, "before"));
+        assert!(!results[1].context);
+        assert_eq!((results[1].column, results[1].text.as_str()), (3, "a foo"));
+        assert_eq!(results[1].submatches, vec![2..5]);
+    }
+
+    #[test]
+    fn clamps_submatches_and_counts_what_isnt_utf8() {
+        let output = [
+            r#"{"type":"match","data":{"path":{"text":"a.rs"},"lines":{"text":"foo\r\n"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":"\r\n"},"start":3,"end":5}]}}"#,
+            r#"{"type":"match","data":{"path":{"text":"a.rs"},"lines":{"bytes":"Zm/vbw=="},"line_number":2,"absolute_offset":5,"submatches":[]}}"#,
+            r#"{"type":"match","data":{"path":{"bytes":"YfgucnM="},"lines":{"text":"foo\n"},"line_number":1,"absolute_offset":0,"submatches":[]}}"#,
+        ]
+        .join("\n");
+
+        let (results, skipped) = parse_matches(output.as_bytes());
+        assert_eq!(skipped, 2);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].submatches, vec![3..3]);
+    }
+}